@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::problem::Problem;
+use vrp_pragmatic::format::solution::{Solution, Tour};
+use vrp_pragmatic::format::Location;
+use warp::http::StatusCode;
+use warp::reject;
+use warp::reply::Response;
+
+use crate::matrix::MatrixSource;
+use crate::profile::TravelProfile;
+use crate::response::ErrorResponse;
+
+const POLYLINE_PRECISION: f64 = 1e5;
+/// How many route requests are in flight at once for a solution, across all of its tours.
+const MAX_CONCURRENT_ROUTES: usize = 4;
+
+/// The distance in metres and duration in seconds between two consecutive stops of a tour.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteLeg {
+    pub distance: f64,
+    pub duration: f64,
+}
+
+/// The road geometry through a list of waypoints, with a leg between every consecutive pair.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Route {
+    pub coordinates: Vec<Location>,
+    pub legs: Vec<RouteLeg>,
+}
+
+/// The road geometry of a tour, encoded as a polyline with a precision of five decimal places.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TourRoute {
+    #[serde(skip)]
+    pub vehicle_id: String,
+    #[serde(skip)]
+    pub shift_index: usize,
+    pub geometry: String,
+    pub distance: f64,
+    pub duration: f64,
+    pub legs: Vec<RouteLeg>,
+    #[serde(skip)]
+    pub coordinates: Vec<Location>,
+}
+
+/// A tour of a solution along with its road geometry, when it was routed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutedTour {
+    #[serde(flatten)]
+    pub tour: Tour,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<TourRoute>,
+}
+
+impl From<Tour> for RoutedTour {
+    fn from(tour: Tour) -> Self {
+        RoutedTour { tour, route: None }
+    }
+}
+
+impl RoutedTour {
+    /// Attaches the route found for the same vehicle and shift, if there is one.
+    pub fn with_route(self, routes: &[TourRoute]) -> RoutedTour {
+        let route = routes
+            .iter()
+            .find(|route| {
+                route.vehicle_id == self.tour.vehicle_id
+                    && route.shift_index == self.tour.shift_index
+            })
+            .cloned();
+        RoutedTour { route, ..self }
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectionsFail {
+    message: String,
+}
+
+impl reject::Reject for DirectionsFail {}
+
+impl DirectionsFail {
+    pub fn new(message: &str) -> DirectionsFail {
+        DirectionsFail {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn to_reply(&self) -> Response {
+        ErrorResponse::new("DIRECTIONS_UNAVAILABLE", &self.message)
+            .into_reply(StatusCode::BAD_GATEWAY)
+    }
+}
+
+/// A routing backend that can give the road geometry through a list of waypoints.
+#[async_trait]
+pub trait RouteProvider: Send + Sync {
+    async fn get_route(
+        &self,
+        waypoints: &[Location],
+        profile: TravelProfile,
+    ) -> Result<Route, DirectionsFail>;
+
+    /// The most waypoints a single route request may have, longer tours are routed in parts.
+    fn max_waypoints(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// The `route` response shared by the Mapbox Directions API and the OSRM `route` service, when
+/// asked for GeoJSON geometries.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectionsResponse {
+    pub code: String,
+    #[serde(default)]
+    pub routes: Vec<DirectionsRoute>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectionsRoute {
+    pub geometry: LineGeometry,
+    pub legs: Vec<RouteLeg>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineGeometry {
+    pub coordinates: Vec<[f64; 2]>,
+}
+
+impl DirectionsResponse {
+    /// The first route of the response, as long as it has a leg between every waypoint.
+    pub fn into_route(self, waypoints: usize) -> Option<Route> {
+        if self.code != "Ok" {
+            return None;
+        }
+
+        self.routes
+            .into_iter()
+            .next()
+            .filter(|route| route.legs.len() + 1 == waypoints)
+            .map(|route| Route {
+                coordinates: route
+                    .geometry
+                    .coordinates
+                    .iter()
+                    .map(|[lng, lat]| Location {
+                        lat: *lat,
+                        lng: *lng,
+                    })
+                    .collect(),
+                legs: route.legs,
+            })
+    }
+}
+
+fn encode_value(value: i64, encoded: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+    while value >= 0x20 {
+        encoded.push((((value & 0x1f) | 0x20) as u8 + 63) as char);
+        value >>= 5;
+    }
+    encoded.push((value as u8 + 63) as char);
+}
+
+/// Encodes locations with the polyline algorithm used by Google, Mapbox and OSRM.
+pub fn encode_polyline(locations: &[Location]) -> String {
+    let mut encoded = String::new();
+    let (mut previous_lat, mut previous_lng) = (0, 0);
+    for location in locations {
+        let lat = (location.lat * POLYLINE_PRECISION).round() as i64;
+        let lng = (location.lng * POLYLINE_PRECISION).round() as i64;
+        encode_value(lat - previous_lat, &mut encoded);
+        encode_value(lng - previous_lng, &mut encoded);
+        previous_lat = lat;
+        previous_lng = lng;
+    }
+    encoded
+}
+
+/// Splits the waypoints into parts of at most `size`, each starting where the previous one ended.
+fn get_parts(waypoints: &[Location], size: usize) -> Vec<&[Location]> {
+    if waypoints.len() <= size {
+        return vec![waypoints];
+    }
+
+    let step = size.max(2) - 1;
+    (0..waypoints.len() - 1)
+        .step_by(step)
+        .map(|start| &waypoints[start..(start + step + 1).min(waypoints.len())])
+        .collect()
+}
+
+/// Joins routes that each start where the previous one ended, dropping the repeated coordinate.
+fn join(routes: Vec<Route>) -> Route {
+    routes
+        .into_iter()
+        .fold(Route::default(), |mut joined, route| {
+            let skip = match (joined.coordinates.last(), route.coordinates.first()) {
+                (Some(last), Some(first)) if last == first => 1,
+                _ => 0,
+            };
+            joined
+                .coordinates
+                .extend(route.coordinates.into_iter().skip(skip));
+            joined.legs.extend(route.legs);
+            joined
+        })
+}
+
+/// Routes through every stop of a tour, in as many requests as the provider needs.
+async fn get_tour_route(
+    provider: &dyn RouteProvider,
+    tour: &Tour,
+    profile: TravelProfile,
+) -> Result<TourRoute, DirectionsFail> {
+    let waypoints: Vec<Location> = tour
+        .stops
+        .iter()
+        .map(|stop| stop.location.clone())
+        .collect();
+    let route = if waypoints.len() < 2 {
+        Route {
+            coordinates: waypoints,
+            legs: vec![],
+        }
+    } else {
+        let parts = get_parts(&waypoints, provider.max_waypoints());
+        let requests: Vec<_> = parts
+            .into_iter()
+            .map(|part| provider.get_route(part, profile))
+            .collect();
+        let routes: Vec<Result<Route, DirectionsFail>> = stream::iter(requests)
+            .buffered(MAX_CONCURRENT_ROUTES)
+            .collect()
+            .await;
+        join(
+            routes
+                .into_iter()
+                .collect::<Result<Vec<Route>, DirectionsFail>>()?,
+        )
+    };
+
+    Ok(TourRoute {
+        vehicle_id: tour.vehicle_id.clone(),
+        shift_index: tour.shift_index,
+        geometry: encode_polyline(&route.coordinates),
+        distance: route.legs.iter().map(|leg| leg.distance).sum(),
+        duration: route.legs.iter().map(|leg| leg.duration).sum(),
+        legs: route.legs,
+        coordinates: route.coordinates,
+    })
+}
+
+/// The travel profile of every vehicle type of the problem, taken from the type of its profile
+/// and falling back to a car for profiles we don't know how to route.
+pub fn get_vehicle_profiles(problem: &Problem) -> HashMap<String, TravelProfile> {
+    problem
+        .fleet
+        .vehicles
+        .iter()
+        .map(|vehicle| {
+            let profile = problem
+                .fleet
+                .profiles
+                .iter()
+                .find(|profile| profile.name == vehicle.profile)
+                .and_then(|profile| TravelProfile::from_name(&profile.profile_type))
+                .or_else(|| TravelProfile::from_name(&vehicle.profile))
+                .unwrap_or_default();
+            (vehicle.type_id.clone(), profile)
+        })
+        .collect()
+}
+
+/// Routes every tour of the solution through the given backend.
+pub async fn route_tours(
+    source: &MatrixSource,
+    solution: &Solution,
+    profiles: &HashMap<String, TravelProfile>,
+) -> Result<Vec<TourRoute>, DirectionsFail> {
+    get_routes(source.get_route_provider().as_ref(), solution, profiles).await
+}
+
+/// Routes every tour of the solution with the travel profile of its vehicle type.
+pub async fn get_routes(
+    provider: &dyn RouteProvider,
+    solution: &Solution,
+    profiles: &HashMap<String, TravelProfile>,
+) -> Result<Vec<TourRoute>, DirectionsFail> {
+    let requests: Vec<_> = solution
+        .tours
+        .iter()
+        .map(|tour| {
+            let profile = profiles.get(&tour.type_id).copied().unwrap_or_default();
+            get_tour_route(provider, tour, profile)
+        })
+        .collect();
+    let routes: Vec<Result<TourRoute, DirectionsFail>> = stream::iter(requests)
+        .buffer_unordered(MAX_CONCURRENT_ROUTES)
+        .collect()
+        .await;
+    routes.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometric::GeometricProvider;
+
+    fn get_locations(count: usize) -> Vec<Location> {
+        (0..count)
+            .map(|index| Location {
+                lat: 51.45 + index as f64 * 0.01,
+                lng: -2.58,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_polyline() {
+        let locations = vec![
+            Location {
+                lat: 38.5,
+                lng: -120.2,
+            },
+            Location {
+                lat: 40.7,
+                lng: -120.95,
+            },
+            Location {
+                lat: 43.252,
+                lng: -126.453,
+            },
+        ];
+        assert_eq!(encode_polyline(&locations), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+        assert_eq!(encode_polyline(&[]), "");
+    }
+
+    #[test]
+    fn test_get_parts() {
+        let locations = get_locations(50);
+        let parts = get_parts(&locations, 25);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].len(), 25);
+        assert_eq!(parts[1][0], parts[0][24]);
+        assert_eq!(parts[2].last(), locations.last());
+        assert_eq!(get_parts(&locations[..3], 25).len(), 1);
+    }
+
+    #[test]
+    fn test_into_route() {
+        let response: DirectionsResponse = serde_json::from_str(
+            r#"{
+  "code": "Ok",
+  "routes": [
+    {
+      "geometry": { "type": "LineString", "coordinates": [[-2.586, 51.455], [-2.584, 51.452], [-2.578, 51.449]] },
+      "legs": [{ "distance": 1020.5, "duration": 240.1, "summary": "" }],
+      "distance": 1020.5,
+      "duration": 240.1
+    }
+  ]
+}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.clone().into_route(3), None);
+        let route = response.into_route(2).unwrap();
+        assert_eq!(route.coordinates.len(), 3);
+        assert_eq!(route.coordinates[1].lat, 51.452);
+        assert_eq!(route.legs[0].distance, 1020.5);
+    }
+
+    #[tokio::test]
+    async fn test_get_tour_route_in_parts() {
+        struct PartsProvider;
+
+        #[async_trait]
+        impl RouteProvider for PartsProvider {
+            async fn get_route(
+                &self,
+                waypoints: &[Location],
+                profile: TravelProfile,
+            ) -> Result<Route, DirectionsFail> {
+                GeometricProvider::default()
+                    .get_route(waypoints, profile)
+                    .await
+            }
+
+            fn max_waypoints(&self) -> usize {
+                3
+            }
+        }
+
+        let tour: Tour = serde_json::from_value(serde_json::json!({
+            "vehicleId": "0",
+            "typeId": "0",
+            "shiftIndex": 0,
+            "stops": get_locations(6).iter().map(|location| serde_json::json!({
+                "location": location,
+                "time": { "arrival": "2020-06-01T09:00:00Z", "departure": "2020-06-01T09:00:00Z" },
+                "distance": 0,
+                "load": [0],
+                "activities": []
+            })).collect::<Vec<serde_json::Value>>(),
+            "statistic": {
+                "cost": 0.0,
+                "distance": 0,
+                "duration": 0,
+                "times": { "driving": 0, "serving": 0, "waiting": 0, "break": 0 }
+            }
+        }))
+        .unwrap();
+
+        let route = get_tour_route(&PartsProvider, &tour, TravelProfile::Car)
+            .await
+            .unwrap();
+        assert_eq!(route.legs.len(), 5);
+        assert_eq!(route.coordinates, get_locations(6));
+        assert_eq!(route.geometry, encode_polyline(&get_locations(6)));
+        assert!(route.distance > 5000.0);
+    }
+}
@@ -0,0 +1,295 @@
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::checker::CheckerContext;
+use vrp_pragmatic::format::problem::{JobTask, Problem};
+
+/// The stages the checker runs in order, stopping at the first one that fails. Breaks are left
+/// out as the checker never enforces them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Constraint {
+    Capacity,
+    Relation,
+    Assignment,
+}
+
+/// The constraint the solution breaks, along with the tour and job it was found in when those
+/// can be worked out from the checker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Violation {
+    pub constraint: Constraint,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vehicle_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+impl Violation {
+    fn new(problem: &Problem, constraint: Constraint, message: String) -> Violation {
+        let quoted = get_quoted_ids(&message);
+        let job_id = quoted
+            .iter()
+            .find(|id| problem.plan.jobs.iter().any(|job| &job.id == *id))
+            .cloned();
+        let vehicle_id = quoted.into_iter().find(|id| is_vehicle(problem, id));
+
+        Violation {
+            constraint,
+            message,
+            vehicle_id,
+            job_id,
+        }
+    }
+}
+
+fn is_vehicle(problem: &Problem, id: &str) -> bool {
+    problem.fleet.vehicles.iter().any(|vehicle| {
+        vehicle
+            .vehicle_ids
+            .iter()
+            .any(|vehicle_id| vehicle_id == id)
+    })
+}
+
+fn get_quoted_ids(message: &str) -> Vec<String> {
+    message
+        .split('\'')
+        .skip(1)
+        .step_by(2)
+        .map(String::from)
+        .collect()
+}
+
+/// Switches the capacity stage off by emptying every capacity, demand and load, all of which the
+/// checker then reads as zero.
+fn without_capacity(context: &CheckerContext) -> CheckerContext {
+    let mut problem = context.problem.clone();
+    problem
+        .fleet
+        .vehicles
+        .iter_mut()
+        .for_each(|vehicle| vehicle.capacity = vec![]);
+    problem
+        .plan
+        .jobs
+        .iter_mut()
+        .flat_map(|job| {
+            vec![
+                &mut job.pickups,
+                &mut job.deliveries,
+                &mut job.replacements,
+                &mut job.services,
+            ]
+        })
+        .flat_map(|tasks| tasks.iter_mut().flatten())
+        .for_each(|task: &mut JobTask| task.demand = None);
+
+    let mut solution = context.solution.clone();
+    solution
+        .tours
+        .iter_mut()
+        .flat_map(|tour| tour.stops.iter_mut())
+        .for_each(|stop| stop.load = vec![]);
+
+    CheckerContext::new(problem, context.matrices.clone(), solution)
+}
+
+/// Switches the relation stage off by dropping every relation.
+fn without_relations(context: CheckerContext) -> CheckerContext {
+    let mut problem = context.problem;
+    problem.plan.relations = None;
+    CheckerContext::new(problem, context.matrices, context.solution)
+}
+
+/// Works out which stage the checker failed at with `message`. Switching off the stages before
+/// and including the failed one changes the outcome of the checker, whereas switching off only
+/// earlier stages gives back the very same failure.
+fn get_failed_stage(context: &CheckerContext, message: &str) -> Constraint {
+    let fails_with_message =
+        |context: &CheckerContext| context.check().err().as_deref() == Some(message);
+
+    let context = without_capacity(context);
+    if !fails_with_message(&context) {
+        return Constraint::Capacity;
+    }
+    if context.problem.plan.relations.is_none() {
+        return Constraint::Assignment;
+    }
+    if !fails_with_message(&without_relations(context)) {
+        return Constraint::Relation;
+    }
+    Constraint::Assignment
+}
+
+/// Checks the solution against its problem, giving back the violation the checker stops at. The
+/// checker is only run again, with some of its stages switched off, to tell which stage failed
+/// when the solution is unfeasible.
+pub fn get_violations(context: &CheckerContext) -> Vec<Violation> {
+    let message = match context.check() {
+        Ok(_) => return vec![],
+        Err(message) => message,
+    };
+
+    let constraint = get_failed_stage(context, &message);
+    let violation = Violation::new(&context.problem, constraint, message);
+    log::warn!("Unfeasible solution: {:?}", violation);
+
+    vec![violation]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vrp_pragmatic::format::problem::{deserialize_problem, Relation, RelationType};
+    use vrp_pragmatic::format::solution::{deserialize_solution, UnassignedJob};
+
+    use std::io::BufReader;
+
+    fn get_context(capacity: i32) -> CheckerContext {
+        let problem = format!(
+            r#"
+{{
+  "plan": {{
+    "jobs": [
+      {{
+        "id": "job1",
+        "deliveries": [
+          {{
+            "places": [{{ "location": {{ "lat": 51.449516, "lng": -2.57837 }}, "duration": 0.0 }}],
+            "demand": [2]
+          }}
+        ]
+      }}
+    ]
+  }},
+  "fleet": {{
+    "vehicles": [
+      {{
+        "typeId": "vehicle",
+        "vehicleIds": ["vehicle_1"],
+        "profile": "car",
+        "costs": {{ "fixed": 0.0, "distance": 0.0, "time": 0.0 }},
+        "shifts": [
+          {{
+            "start": {{
+              "time": "1970-01-01T00:00:00Z",
+              "location": {{ "lat": 51.455691, "lng": -2.586119 }}
+            }}
+          }}
+        ],
+        "capacity": [{}]
+      }}
+    ],
+    "profiles": [{{ "name": "car", "type": "car" }}]
+  }}
+}}"#,
+            capacity
+        );
+        let solution = r#"
+{
+  "statistic": {
+    "cost": 0.0,
+    "distance": 1,
+    "duration": 1,
+    "times": { "driving": 1, "serving": 0, "waiting": 0, "break": 0 }
+  },
+  "tours": [
+    {
+      "vehicleId": "vehicle_1",
+      "typeId": "vehicle",
+      "shiftIndex": 0,
+      "stops": [
+        {
+          "location": { "lat": 51.455691, "lng": -2.586119 },
+          "time": { "arrival": "1970-01-01T00:00:00Z", "departure": "1970-01-01T00:00:00Z" },
+          "distance": 0,
+          "load": [2],
+          "activities": [{ "jobId": "departure", "type": "departure" }]
+        },
+        {
+          "location": { "lat": 51.449516, "lng": -2.57837 },
+          "time": { "arrival": "1970-01-01T00:00:01Z", "departure": "1970-01-01T00:00:01Z" },
+          "distance": 1,
+          "load": [0],
+          "activities": [{ "jobId": "job1", "type": "delivery" }]
+        }
+      ],
+      "statistic": {
+        "cost": 0.0,
+        "distance": 1,
+        "duration": 1,
+        "times": { "driving": 1, "serving": 0, "waiting": 0, "break": 0 }
+      }
+    }
+  ],
+  "unassigned": []
+}"#;
+
+        CheckerContext::new(
+            deserialize_problem(BufReader::new(problem.as_bytes())).unwrap(),
+            None,
+            deserialize_solution(BufReader::new(solution.as_bytes())).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_feasible_solution_has_no_violations() {
+        assert!(get_violations(&get_context(2)).is_empty());
+    }
+
+    #[test]
+    fn test_get_capacity_violation() {
+        let violations = get_violations(&get_context(1));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].constraint, Constraint::Capacity);
+        assert_eq!(violations[0].vehicle_id, Some("vehicle_1".to_string()));
+        assert_eq!(violations[0].job_id, None);
+    }
+
+    #[test]
+    fn test_get_relation_violation() {
+        let context = get_context(2);
+        let mut problem = context.problem.clone();
+        problem.plan.relations = Some(vec![Relation {
+            type_field: RelationType::Strict,
+            jobs: vec!["job1".to_string()],
+            vehicle_id: "vehicle_2".to_string(),
+            shift_index: None,
+        }]);
+        let context = CheckerContext::new(problem, None, context.solution);
+
+        let violations = get_violations(&context);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].constraint, Constraint::Relation);
+    }
+
+    #[test]
+    fn test_get_assignment_violation() {
+        let context = get_context(1);
+        let mut solution = context.solution.clone();
+        solution.unassigned = vec![UnassignedJob {
+            job_id: "job1".to_string(),
+            reasons: vec![],
+        }];
+        solution.tours[0]
+            .stops
+            .iter_mut()
+            .for_each(|stop| stop.load = vec![0]);
+        let context = CheckerContext::new(context.problem, None, solution);
+
+        let violations = get_violations(&without_capacity(&context));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].constraint, Constraint::Assignment);
+        assert_eq!(violations[0].job_id, Some("job1".to_string()));
+    }
+
+    #[test]
+    fn test_get_quoted_ids() {
+        assert_eq!(
+            get_quoted_ids("Job present as assigned and unassigned: 'job1'"),
+            vec!["job1".to_string()]
+        );
+        assert!(get_quoted_ids("Duplicated job ids").is_empty());
+    }
+}
@@ -1,219 +1,776 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs::File;
+use std::sync::Arc;
 
-use csv::{ByteRecord, Reader};
-use serde::Deserialize;
+use csv::Reader;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
 use vrp_pragmatic::format::Location;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{reject, Rejection, Reply};
 
+use crate::memory_geocoder::MemoryGeocoder;
+use crate::postcode::{Postcode, PostcodeError};
 use crate::redis_manager;
-use crate::user::get_user_from_token;
-use failure::_core::convert::Infallible;
+use crate::spatial::NearbyPostcode;
+use crate::user::get_user;
 
-#[derive(Deserialize)]
+/// A single postcode or pair of coordinates to geocode on `POST /geocoding`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Geocoding {
     pub query: GeocodingKind,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub enum GeocodingKind {
     POSTCODE(String),
     COORDINATES(Vec<f64>),
 }
 
-cached! {
-    POSTCODES;
-    fn bootstrap_cache(table: String) -> Option<()> = {
-        match table.as_str() {
-            POSTCODE_TABLE_NAME => {
-                // I don't want to read these again to UTF so using a known const
-                let postcode_csv_size = 2628568;
-                let mut reader = crate::geocoding::read_geocoding_csv();
-                let count = redis_manager::count(POSTCODE_TABLE_NAME);
-
-                if count < postcode_csv_size {
-                    log::info!("Bootstrapping postcode cache");
-                    redis_manager::bulk_set(&mut reader, POSTCODE_TABLE_NAME);
-                    Some(())
-                } else {
-                    log::info!("Postcode cache was already bootstrapped");
-                    Some(())
-                }
-            }
-            _ => {
-                log::error!("No available table named {}", table);
-                None
+/// Many postcodes and coordinates to geocode at once.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BatchGeocoding {
+    pub queries: Vec<GeocodingKind>,
+}
+
+/// Where the answer to a geocoding query was found.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeocodingSource {
+    Redis,
+    Memory,
+}
+
+/// Whether a postcode was found as given, or is the nearest one to the coordinates queried.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    Exact,
+    Nearest,
+}
+
+/// A geocoded postcode along with its coordinates, whichever of the two was queried.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeocodingResponse {
+    pub postcode: String,
+    pub lat: f64,
+    pub lng: f64,
+    pub source: GeocodingSource,
+    pub match_type: MatchType,
+    /// How far away the postcode is from the coordinates in metres, for nearest matches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+}
+
+impl GeocodingResponse {
+    fn exact(postcode: &Postcode, location: Location, source: GeocodingSource) -> Self {
+        GeocodingResponse {
+            postcode: postcode.to_string(),
+            lat: location.lat,
+            lng: location.lng,
+            source,
+            match_type: MatchType::Exact,
+            distance: None,
+        }
+    }
+
+    fn nearest(nearest: NearbyPostcode) -> Self {
+        GeocodingResponse {
+            postcode: nearest.postcode,
+            lat: nearest.location.lat,
+            lng: nearest.location.lng,
+            source: GeocodingSource::Memory,
+            match_type: MatchType::Nearest,
+            distance: Some(nearest.distance),
+        }
+    }
+}
+
+/// The answer to a query of a batch, or why the query couldn't be resolved.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum GeocodingResult {
+    Found(GeocodingResponse),
+    Error { error: GeocodingFail },
+}
+
+impl From<Result<GeocodingResponse, GeocodingFail>> for GeocodingResult {
+    fn from(result: Result<GeocodingResponse, GeocodingFail>) -> Self {
+        match result {
+            Ok(response) => GeocodingResult::Found(response),
+            Err(error) => GeocodingResult::Error { error },
+        }
+    }
+}
+
+/// Query parameters of the nearby postcode search, at least one of which has to be given.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct NearbyQuery {
+    pub k: Option<usize>,
+    pub radius_m: Option<f64>,
+}
+
+impl NearbyQuery {
+    /// Keeps searches to a neighbourhood, so that a single query can't list the whole country.
+    pub fn validate(&self) -> Result<(), GeocodingFail> {
+        let invalid = |message: &str| {
+            Err(GeocodingFail::new(
+                GeocodingErrorCode::InvalidQuery,
+                message,
+            ))
+        };
+
+        match (self.k, self.radius_m) {
+            (None, None) => invalid("Either k or radius_m has to be given"),
+            (Some(k), _) if !(1..=MAX_NEARBY_POSTCODES).contains(&k) => invalid(&format!(
+                "k has to be between 1 and {}, not {}",
+                MAX_NEARBY_POSTCODES, k
+            )),
+            (_, Some(radius)) if !(0.0..=MAX_NEARBY_RADIUS_METRES).contains(&radius) => {
+                invalid(&format!(
+                    "radius_m has to be between 0 and {}, not {}",
+                    MAX_NEARBY_RADIUS_METRES, radius
+                ))
             }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Machine readable reasons for a geocoding query failing, each replied with its own status.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum GeocodingErrorCode {
+    InvalidPostcode,
+    InvalidCoordinates,
+    InvalidQuery,
+    PostcodeNotFound,
+    UnresolvedLocations,
+    GeocoderUnavailable,
+}
+
+impl GeocodingErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            GeocodingErrorCode::InvalidPostcode
+            | GeocodingErrorCode::InvalidCoordinates
+            | GeocodingErrorCode::InvalidQuery => StatusCode::BAD_REQUEST,
+            GeocodingErrorCode::PostcodeNotFound => StatusCode::NOT_FOUND,
+            GeocodingErrorCode::UnresolvedLocations => StatusCode::UNPROCESSABLE_ENTITY,
+            GeocodingErrorCode::GeocoderUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeocodingFail {
+    code: GeocodingErrorCode,
+    message: String,
+    /// Every location of a request that couldn't be geocoded, when there are any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unresolved: Vec<UnresolvedLocation>,
+}
+
+/// Which list of a request an unresolved location belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationEntry {
+    Vehicle,
+    Job,
+    Origin,
+    Destination,
+}
+
+/// A postcode of a request that couldn't be geocoded, along with the index of the vehicle, job or
+/// matrix point it was given for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnresolvedLocation {
+    pub entry: LocationEntry,
+    pub index: usize,
+    pub postcode: String,
+    pub error: GeocodingFail,
+}
+
+impl reject::Reject for GeocodingFail {}
+
+impl GeocodingFail {
+    pub fn new(code: GeocodingErrorCode, message: &str) -> GeocodingFail {
+        GeocodingFail {
+            code,
+            message: message.to_string(),
+            unresolved: vec![],
         }
     }
+
+    pub fn unresolved(unresolved: Vec<UnresolvedLocation>) -> GeocodingFail {
+        GeocodingFail {
+            unresolved,
+            ..GeocodingFail::new(
+                GeocodingErrorCode::UnresolvedLocations,
+                "Some of the locations couldn't be geocoded",
+            )
+        }
+    }
+
+    fn not_found(postcode: &Postcode) -> GeocodingFail {
+        GeocodingFail::new(
+            GeocodingErrorCode::PostcodeNotFound,
+            &format!("Unable to find the postcode {}", postcode),
+        )
+    }
+
+    fn unavailable() -> GeocodingFail {
+        GeocodingFail::new(
+            GeocodingErrorCode::GeocoderUnavailable,
+            "Postcodes haven't been loaded",
+        )
+    }
+
+    pub fn code(&self) -> GeocodingErrorCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn to_reply(&self) -> Response {
+        warp::reply::with_status(
+            warp::reply::json(&GeocodingResult::Error {
+                error: self.clone(),
+            }),
+            self.code.status(),
+        )
+        .into_response()
+    }
+}
+
+impl From<PostcodeError> for GeocodingFail {
+    fn from(error: PostcodeError) -> Self {
+        GeocodingFail::new(GeocodingErrorCode::InvalidPostcode, &error.to_string())
+    }
+}
+
+static POSTCODE_CACHE: OnceCell<bool> = OnceCell::new();
+static MEMORY_GEOCODER: OnceCell<Option<Arc<MemoryGeocoder>>> = OnceCell::new();
+
+/// Fills the redis postcode table from the postcode dataset unless it is already full. Only ever
+/// runs once, however many requests arrive while it does.
+fn bootstrap_postcode_cache() -> bool {
+    if !use_redis() {
+        log::info!("Geocoding from memory without redis");
+        return false;
+    }
+    // I don't want to read these again to UTF so using a known const
+    let postcode_csv_size = 2628568;
+    if redis_manager::count(POSTCODE_TABLE_NAME) < postcode_csv_size {
+        log::info!("Bootstrapping postcode cache");
+        read_geocoding_csv()
+            .and_then(|mut reader| redis_manager::bulk_set(&mut reader, POSTCODE_TABLE_NAME))
+            .is_some()
+    } else {
+        log::info!("Postcode cache was already bootstrapped");
+        true
+    }
 }
 
+pub fn get_memory_geocoder() -> Option<Arc<MemoryGeocoder>> {
+    MEMORY_GEOCODER
+        .get_or_init(|| {
+            log::info!("Loading postcodes into memory");
+            let geocoder = MemoryGeocoder::from_csv(POSTCODES_CSV)?;
+            log::info!("Finished loading {} postcodes", geocoder.len());
+            Some(Arc::new(geocoder))
+        })
+        .clone()
+}
+
+/// Postcodes are looked up in redis unless `GEOCODER=memory` is set or redis can't be reached, in
+/// which case they are served from memory instead.
+fn use_redis() -> bool {
+    let memory = env::var("GEOCODER").is_ok_and(|geocoder| geocoder == "memory");
+    !memory && redis_manager::is_available()
+}
+
+pub const POSTCODES_CSV: &str = "postcodes.csv";
 pub const POSTCODE_TABLE_NAME: &str = "POSTCODE";
 pub const COORDINATES_SEPARATOR: &str = ";";
+/// The most postcodes a nearby search lists.
+const MAX_NEARBY_POSTCODES: usize = 100;
+/// The widest radius a nearby search looks in.
+const MAX_NEARBY_RADIUS_METRES: f64 = 50_000.0;
 
-pub fn lookup_coordinates(query: String) -> Location {
-    let coordinates: String = reverse_search(query.clone());
-    let coordinates: Vec<&str> = coordinates.split(';').collect();
-    Location {
-        lat: coordinates[0].parse().unwrap_or_else(|_| {
-            println!(
-                "There weren't enough coordinates to extract latitude for postcode {}",
-                query
-            );
-            0.0
-        }),
-        lng: coordinates[1].parse().unwrap_or_else(|_| {
-            println!(
-                "There weren't enough coordinates to extract longitude for postcode {}",
-                query
-            );
-            0.0
-        }),
-    }
+/// The coordinates of a postcode, or why it couldn't be geocoded.
+pub fn lookup_coordinates(query: &str) -> Result<Location, GeocodingFail> {
+    let postcode = Postcode::parse(query)?;
+    search_postcode(&postcode)
+        .inspect_err(|error| log::warn!("Unable to geocode {}: {}", query, error.message()))
 }
 
 pub fn get_postcodes() -> bool {
-    let bootstrapped = bootstrap_cache(POSTCODE_TABLE_NAME.to_string());
-    bootstrapped == Some(())
+    *POSTCODE_CACHE.get_or_init(bootstrap_postcode_cache)
 }
 
-pub fn reverse_search(query: String) -> String {
+fn get_source() -> GeocodingSource {
     if get_postcodes() {
-        match reverse_search_cache(query) {
-            Some(value) => value,
-            None => String::from("EMPTY"), //TODO this is a poop error message
-        }
+        GeocodingSource::Redis
     } else {
-        reverse_search_file(query)
+        GeocodingSource::Memory
+    }
+}
+
+/// The coordinates of a postcode, from redis or otherwise from memory.
+pub fn search_postcode(postcode: &Postcode) -> Result<Location, GeocodingFail> {
+    let coordinates = lookup_many_coordinates(std::slice::from_ref(postcode), get_source())?;
+    coordinates
+        .into_iter()
+        .flatten()
+        .next()
+        .ok_or_else(|| GeocodingFail::not_found(postcode))
+}
+
+/// Looks up the coordinates of many postcodes at once, with `None` for any that don't exist.
+fn lookup_many_coordinates(
+    postcodes: &[Postcode],
+    source: GeocodingSource,
+) -> Result<Vec<Option<Location>>, GeocodingFail> {
+    if postcodes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    match source {
+        GeocodingSource::Redis => {
+            let keys: Vec<String> = postcodes.iter().map(build_cache_key).collect();
+            let coordinates = redis_manager::get_many_coordinates(&keys)
+                .ok_or_else(GeocodingFail::unavailable)?;
+            Ok(coordinates
+                .into_iter()
+                .map(|value| parse_coordinates(&value?))
+                .collect())
+        }
+        GeocodingSource::Memory => {
+            let geocoder = get_memory_geocoder().ok_or_else(GeocodingFail::unavailable)?;
+            Ok(postcodes
+                .iter()
+                .map(|postcode| geocoder.get_coordinates(postcode))
+                .collect())
+        }
     }
 }
 
-pub fn reverse_search_cache(query: String) -> Option<String> {
-    let postcode = build_cache_key(query);
-    let postcode = postcode.as_str();
+fn build_cache_key(postcode: &Postcode) -> String {
+    postcode.to_key()
+}
 
-    redis_manager::get_coordinates(postcode)
+/// The postcode whose centroid is closest to the coordinates, from the index built over the
+/// postcode dataset.
+pub fn nearest_postcode(lat: f64, lng: f64) -> Option<NearbyPostcode> {
+    get_memory_geocoder()?.nearest(&Location { lat, lng })
 }
 
-fn build_cache_key(query: String) -> String {
-    // TODO [#39]: sort this out, rust doesn't like fluent that much
-    let postcode = query;
-    let postcode = postcode.replace(" ", "");
-    let postcode = postcode.replace("-", "");
-    let postcode = postcode.replace(",", "");
-    postcode.replace(COORDINATES_SEPARATOR, "")
+/// The `k` postcodes nearest to the coordinates, those within `radius_m` metres of them, or both.
+pub fn nearby_postcodes(lat: f64, lng: f64, query: &NearbyQuery) -> Option<Vec<NearbyPostcode>> {
+    Some(get_memory_geocoder()?.nearby(&Location { lat, lng }, query.k, query.radius_m))
 }
 
-pub fn reverse_search_file(query: String) -> String {
-    let lat_index = 1;
-    let lon_index = 2;
-    let res: ByteRecord = read_geocoding_csv()
-        .byte_records()
-        .find(|record| {
-            record
-                .as_ref()
-                .expect("Couldn't serialise record to a byte record")
-                .iter()
-                .any(|field| field == query.replace(" ", "").replace("-", " ").as_bytes())
-        })
-        .unwrap_or_else(|| panic!("Unable to find coordinates for {}", query))
-        .expect("Find result could not be unwrapped!");
+fn parse_coordinates(coordinates: &str) -> Option<Location> {
+    let mut coordinates = coordinates.trim_matches('"').split(COORDINATES_SEPARATOR);
+    Some(Location {
+        lat: coordinates.next()?.parse().ok()?,
+        lng: coordinates.next()?.parse().ok()?,
+    })
+}
 
-    format!(
-        "{};{}",
-        std::str::from_utf8(res.get(lat_index).unwrap().to_owned().as_ref())
-            .expect("Unable to unwrap latitude"),
-        std::str::from_utf8(res.get(lon_index).unwrap().to_owned().as_ref())
-            .expect("Unable to unwrap longitude")
-    )
+/// The postcode nearest to a latitude and longitude.
+pub fn search_coordinates(lat_lng: &[f64]) -> Result<GeocodingResponse, GeocodingFail> {
+    let location = match lat_lng {
+        [lat, lng] => validate_location(Location {
+            lat: *lat,
+            lng: *lng,
+        })?,
+        _ => {
+            return Err(GeocodingFail::new(
+                GeocodingErrorCode::InvalidCoordinates,
+                &format!(
+                    "Coordinates must be a latitude and longitude, not {:?}",
+                    lat_lng
+                ),
+            ))
+        }
+    };
+
+    let geocoder = get_memory_geocoder().ok_or_else(GeocodingFail::unavailable)?;
+    geocoder
+        .nearest(&location)
+        .map(GeocodingResponse::nearest)
+        .ok_or_else(|| {
+            GeocodingFail::new(
+                GeocodingErrorCode::PostcodeNotFound,
+                &format!("Unable to find a postcode near {:?}", lat_lng),
+            )
+        })
 }
 
-pub fn forward_search(lat_long: Vec<f64>) -> String {
-    if get_postcodes() {
-        forward_search_cache(lat_long)
+/// Checks that the latitude is within 90 degrees and the longitude within 180 degrees either way.
+pub fn validate_location(location: Location) -> Result<Location, GeocodingFail> {
+    if location.lat.abs() <= 90.0 && location.lng.abs() <= 180.0 {
+        Ok(location)
     } else {
-        forward_search_file(lat_long)
+        Err(GeocodingFail::new(
+            GeocodingErrorCode::InvalidCoordinates,
+            &format!(
+                "{},{} isn't a latitude between -90 and 90 and a longitude between -180 and 180",
+                location.lat, location.lng
+            ),
+        ))
     }
 }
 
-pub fn forward_search_cache(lat_long: Vec<f64>) -> String {
-    match redis_manager::get_postcode(lat_long) {
-        None => String::from("Postcode couldn't be found"),
-        Some(value) => value,
+/// Geocodes a postcode to its coordinates, or coordinates to their nearest postcode.
+pub fn geocode(query: &GeocodingKind) -> Result<GeocodingResponse, GeocodingFail> {
+    match query {
+        GeocodingKind::POSTCODE(postcode) => {
+            let postcode = Postcode::parse(postcode)?;
+            let location = search_postcode(&postcode)?;
+            Ok(GeocodingResponse::exact(&postcode, location, get_source()))
+        }
+        GeocodingKind::COORDINATES(lat_lng) => search_coordinates(lat_lng),
     }
 }
 
-pub fn forward_search_file(lat_lon: Vec<f64>) -> String {
-    let postcode_index = 0;
-    let res: ByteRecord = read_geocoding_csv()
-        .byte_records()
-        .find(|record| {
-            record
-                .as_ref()
-                .expect("Couldn't serialise record to a byte record")
-                .iter()
-                .any(|field| field == lat_lon.first().unwrap().to_string().as_bytes())
+/// The coordinates of every postcode in order, or why each couldn't be found.
+pub fn resolve_postcodes(postcodes: &[String]) -> Vec<Result<Location, GeocodingFail>> {
+    let queries: Vec<GeocodingKind> = postcodes
+        .iter()
+        .cloned()
+        .map(GeocodingKind::POSTCODE)
+        .collect();
+    batch_search(&queries)
+        .into_iter()
+        .map(|result| match result {
+            GeocodingResult::Found(found) => Ok(Location {
+                lat: found.lat,
+                lng: found.lng,
+            }),
+            GeocodingResult::Error { error } => Err(error),
+        })
+        .collect()
+}
+
+/// Geocodes every query in order, looking all of the postcodes up in a single round trip.
+pub fn batch_search(queries: &[GeocodingKind]) -> Vec<GeocodingResult> {
+    // only valid postcodes are worth looking up
+    let postcodes: Vec<Postcode> = queries
+        .iter()
+        .filter_map(|query| match query {
+            GeocodingKind::POSTCODE(postcode) => Postcode::parse(postcode).ok(),
+            GeocodingKind::COORDINATES(_) => None,
+        })
+        .collect();
+    let source = get_source();
+    let found: Result<HashMap<Postcode, Option<Location>>, GeocodingFail> =
+        lookup_many_coordinates(&postcodes, source)
+            .map(|coordinates| postcodes.iter().cloned().zip(coordinates).collect());
+
+    queries
+        .iter()
+        .map(|query| -> Result<GeocodingResponse, GeocodingFail> {
+            match query {
+                GeocodingKind::POSTCODE(postcode) => {
+                    let postcode = Postcode::parse(postcode)?;
+                    let location = found
+                        .as_ref()
+                        .map_err(Clone::clone)?
+                        .get(&postcode)
+                        .cloned()
+                        .flatten()
+                        .ok_or_else(|| GeocodingFail::not_found(&postcode))?;
+                    Ok(GeocodingResponse::exact(&postcode, location, source))
+                }
+                GeocodingKind::COORDINATES(lat_lng) => search_coordinates(lat_lng),
+            }
         })
-        .unwrap_or_else(|| panic!("Unable to find a postcode for {:?}", lat_lon))
-        .expect("Find result could not be unwrapped!");
-    String::from_utf8(res.get(postcode_index).unwrap().to_owned())
-        .expect("Unable to unwrap postcode")
+        .map(GeocodingResult::from)
+        .collect()
 }
 
-pub fn read_geocoding_csv() -> Reader<File> {
-    csv::Reader::from_path("postcodes.csv").expect("Issue reading postcodes.csv")
+pub fn read_geocoding_csv() -> Option<Reader<File>> {
+    csv::Reader::from_path(POSTCODES_CSV)
+        .map_err(|error| log::error!("Issue reading {}: {}", POSTCODES_CSV, error))
+        .ok()
+}
+
+pub async fn receive_and_geocode(
+    token: String,
+    geocoding: Geocoding,
+) -> Result<impl warp::Reply, Rejection> {
+    // get_user_from_token(token).await.unwrap();
+    let response = geocode(&geocoding.query).map_err(reject::custom)?;
+    Ok(warp::reply::json(&response))
 }
 
 pub async fn receive_and_search_coordinates(
     token: String,
     postcode: String,
-) -> Result<impl warp::Reply, Infallible> {
+) -> Result<impl warp::Reply, Rejection> {
     // get_user_from_token(token).await.unwrap();
-    let result = reverse_search(postcode);
-    Ok(result)
+    let response = geocode(&GeocodingKind::POSTCODE(postcode)).map_err(reject::custom)?;
+    Ok(warp::reply::json(&response))
 }
 
 pub async fn receive_and_search_postcode(
     lat: f64,
     lon: f64,
     token: String,
-) -> Result<impl warp::Reply, Infallible> {
+) -> Result<impl warp::Reply, Rejection> {
     // get_user_from_token(token).await.unwrap();
-    let result = forward_search(vec![lat, lon]);
-    Ok(result)
+    let response = geocode(&GeocodingKind::COORDINATES(vec![lat, lon])).map_err(reject::custom)?;
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn receive_and_search_nearby_postcodes(
+    lat: f64,
+    lon: f64,
+    token: String,
+    query: NearbyQuery,
+) -> Result<impl warp::Reply, Rejection> {
+    get_user(token).await?;
+    let location = validate_location(Location { lat, lng: lon }).map_err(reject::custom)?;
+    query.validate().map_err(reject::custom)?;
+
+    let nearby =
+        tokio::task::spawn_blocking(move || nearby_postcodes(location.lat, location.lng, &query))
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| reject::custom(GeocodingFail::unavailable()))?;
+    Ok(warp::reply::json(&nearby))
+}
+
+pub async fn receive_and_batch_search(
+    token: String,
+    batch: BatchGeocoding,
+) -> Result<impl warp::Reply, Rejection> {
+    get_user(token).await?;
+    let results = tokio::task::spawn_blocking(move || batch_search(&batch.queries))
+        .await
+        .map_err(|_| {
+            reject::custom(GeocodingFail::new(
+                GeocodingErrorCode::GeocoderUnavailable,
+                "Unable to geocode the batch",
+            ))
+        })?;
+    Ok(warp::reply::json(&results))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::geocoding::{
-        build_cache_key, forward_search_file, get_postcodes, reverse_search_file,
-        COORDINATES_SEPARATOR,
+        batch_search, parse_coordinates, BatchGeocoding, GeocodingErrorCode, GeocodingFail,
+        GeocodingKind, GeocodingResponse, GeocodingResult, GeocodingSource, MatchType,
+    };
+    use crate::geocoding::{
+        build_cache_key, get_postcodes, lookup_coordinates, lookup_many_coordinates,
+        nearby_postcodes, nearest_postcode, NearbyQuery, COORDINATES_SEPARATOR,
     };
+    use crate::postcode::{Postcode, PostcodeError};
+    use vrp_pragmatic::format::Location;
+
+    #[test]
+    fn test_nearest_postcode() {
+        let nearest = nearest_postcode(57.099011, -2.252854).unwrap();
+        assert_eq!(nearest.postcode, "AB1 0AJ");
+        assert_eq!(nearest.distance, 0.0);
+    }
+
+    #[test]
+    fn test_nearby_postcodes() {
+        let query = NearbyQuery {
+            k: Some(10),
+            radius_m: None,
+        };
+        let nearby = nearby_postcodes(57.099011, -2.252854, &query).unwrap();
+        assert_eq!(nearby.len(), 10);
+        assert_eq!(nearby[0].postcode, "AB1 0AJ");
+
+        let query = NearbyQuery {
+            k: None,
+            radius_m: Some(5000.0),
+        };
+        let nearby = nearby_postcodes(57.099011, -2.252854, &query).unwrap();
+        assert!(nearby.iter().all(|postcode| postcode.distance <= 5000.0));
+    }
+
+    #[test]
+    fn test_validate_nearby_query() {
+        let query = |k: Option<usize>, radius_m: Option<f64>| NearbyQuery { k, radius_m };
+        assert!(query(Some(10), None).validate().is_ok());
+        assert!(query(None, Some(5000.0)).validate().is_ok());
+        assert!(query(Some(100), Some(50_000.0)).validate().is_ok());
+
+        for invalid in &[
+            query(None, None),
+            query(Some(0), None),
+            query(Some(101), None),
+            query(None, Some(-1.0)),
+            query(None, Some(50_001.0)),
+            query(None, Some(f64::NAN)),
+        ] {
+            let error = invalid.validate().unwrap_err();
+            assert_eq!(error.code(), GeocodingErrorCode::InvalidQuery);
+        }
+    }
+
+    #[test]
+    fn test_deserialise_batch() {
+        let batch: BatchGeocoding = serde_json::from_str(
+            r#"{"queries": [{"POSTCODE": "AB1 0AJ"}, {"COORDINATES": [57.099011, -2.252854]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            batch.queries,
+            vec![
+                GeocodingKind::POSTCODE("AB1 0AJ".to_string()),
+                GeocodingKind::COORDINATES(vec![57.099011, -2.252854])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        let location = parse_coordinates("\"57.099011;-2.252854\"").unwrap();
+        assert_eq!(location.lat, 57.099011);
+        assert_eq!(location.lng, -2.252854);
+        assert_eq!(parse_coordinates("57.099011"), None);
+    }
+
+    #[test]
+    fn test_batch_search_invalid_coordinates() {
+        let results = batch_search(&[
+            GeocodingKind::COORDINATES(vec![57.0]),
+            GeocodingKind::COORDINATES(vec![91.0, 0.0]),
+        ]);
+        assert!(results.iter().all(|result| match result {
+            GeocodingResult::Error { error } => {
+                error.code() == GeocodingErrorCode::InvalidCoordinates
+            }
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_batch_search_invalid_postcodes() {
+        let results = batch_search(&[
+            GeocodingKind::POSTCODE("BS6 666".to_string()),
+            GeocodingKind::POSTCODE("".to_string()),
+        ]);
+        assert_eq!(
+            results,
+            vec![
+                GeocodingResult::Error {
+                    error: PostcodeError::InvalidInward("666".to_string()).into()
+                },
+                GeocodingResult::Error {
+                    error: PostcodeError::Empty.into()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_search() {
+        let results = batch_search(&[
+            GeocodingKind::POSTCODE("AB1-0AJ".to_string()),
+            GeocodingKind::POSTCODE("NOT A POSTCODE".to_string()),
+            GeocodingKind::COORDINATES(vec![57.099011, -2.252854]),
+        ]);
+        match &results[0] {
+            GeocodingResult::Found(found) => {
+                assert_eq!(found.postcode, "AB1 0AJ");
+                assert_eq!(found.lat, 57.099011);
+                assert_eq!(found.match_type, MatchType::Exact);
+            }
+            result => panic!("Expected coordinates but got {:?}", result),
+        }
+        assert!(matches!(results[1], GeocodingResult::Error { .. }));
+        match &results[2] {
+            GeocodingResult::Found(found) => {
+                assert_eq!(found.postcode, "AB1 0AJ");
+                assert_eq!(found.match_type, MatchType::Nearest);
+            }
+            result => panic!("Expected a postcode but got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_serialise_responses() {
+        let response = GeocodingResponse {
+            postcode: "AB1 0AJ".to_string(),
+            lat: 57.099011,
+            lng: -2.252854,
+            source: GeocodingSource::Redis,
+            match_type: MatchType::Exact,
+            distance: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({"postcode": "AB1 0AJ", "lat": 57.099011, "lng": -2.252854, "source": "redis", "matchType": "exact"})
+        );
+
+        let error = GeocodingResult::Error {
+            error: GeocodingFail::new(GeocodingErrorCode::PostcodeNotFound, "Not here"),
+        };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({"error": {"code": "POSTCODE_NOT_FOUND", "message": "Not here"}})
+        );
+        assert_eq!(
+            GeocodingErrorCode::PostcodeNotFound.status(),
+            warp::http::StatusCode::NOT_FOUND
+        );
+    }
 
     #[test]
-    fn test_search_postcode() {
-        let coordinates = vec![57.099011, -2.252854];
-        let postcode = forward_search_file(coordinates);
-        assert_eq!(postcode, "AB1 0AJ")
+    fn test_bootstrap_postcode_cache_in_redis() {
+        assert!(get_postcodes());
+        let postcode = Postcode::parse("AB1 0AJ").unwrap();
+        let coordinates = lookup_many_coordinates(&[postcode], GeocodingSource::Redis).unwrap();
+        assert!(coordinates[0].is_some());
     }
 
     #[test]
-    fn test_search_coordinates() {
-        let coordinates = reverse_search_file(String::from("AB1-0AJ"));
-        assert_eq!(coordinates, "57.099011;-2.252854")
+    fn test_lookup_postcodes_in_memory() {
+        let postcodes = vec![
+            Postcode::parse("AB1 0AJ").unwrap(),
+            Postcode::parse("ZZ1 1ZZ").unwrap(),
+        ];
+        let coordinates = lookup_many_coordinates(&postcodes, GeocodingSource::Memory).unwrap();
+        assert_eq!(
+            coordinates[0],
+            Some(Location {
+                lat: 57.099011,
+                lng: -2.252854
+            })
+        );
+        assert_eq!(coordinates[1], None);
     }
 
     #[test]
-    fn test_bootstrap_postcode_cache() {
-        assert_eq!(get_postcodes(), true);
+    fn test_lookup_invalid_coordinates() {
+        let error = lookup_coordinates("EMPTY").unwrap_err();
+        assert_eq!(error.code(), GeocodingErrorCode::InvalidPostcode);
     }
 
     #[test]
     fn test_build_cache_key() {
-        let key = "IMAGINARY; -,POSTCODE";
-        let key = build_cache_key(String::from(key));
+        let postcode = Postcode::parse("bs1-3aa").unwrap();
+        let key = build_cache_key(&postcode);
         assert!(!key.contains(' '));
         assert!(!key.contains('-'));
-        assert!(!key.contains(','));
         assert!(!key.contains(COORDINATES_SEPARATOR));
-        assert_eq!(key, "IMAGINARYPOSTCODE")
+        assert_eq!(key, "BS13AA")
     }
 }
@@ -0,0 +1,227 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use vrp_pragmatic::format::solution::{Stop, Tour};
+
+use crate::directions::{RoutedTour, TourRoute};
+
+pub const GEO_JSON_CONTENT_TYPE: &str = "application/geo+json";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point { coordinates: [f64; 2] },
+    LineString { coordinates: Vec<[f64; 2]> },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    pub properties: Map<String, Value>,
+    pub geometry: Geometry,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+fn into_properties(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+fn get_stop_coordinates(stop: &Stop) -> [f64; 2] {
+    [stop.location.lng, stop.location.lat]
+}
+
+fn get_stop_point(tour: &Tour, sequence: usize, stop: &Stop) -> Feature {
+    Feature {
+        properties: into_properties(json!({
+            "vehicleId": tour.vehicle_id,
+            "sequence": sequence,
+            "arrival": stop.time.arrival,
+            "departure": stop.time.departure,
+            "jobIds": stop
+                .activities
+                .iter()
+                .map(|activity| activity.job_id.clone())
+                .collect::<Vec<String>>(),
+            "activityTypes": stop
+                .activities
+                .iter()
+                .map(|activity| activity.activity_type.clone())
+                .collect::<Vec<String>>(),
+            "load": stop.load,
+        })),
+        geometry: Geometry::Point {
+            coordinates: get_stop_coordinates(stop),
+        },
+    }
+}
+
+/// Draws the tour along its road geometry when it was routed, or straight between its stops.
+fn get_tour_line(tour: &Tour, route: Option<&TourRoute>) -> Feature {
+    Feature {
+        properties: into_properties(json!({
+            "vehicleId": tour.vehicle_id,
+            "typeId": tour.type_id,
+            "shiftIndex": tour.shift_index,
+            "stops": tour.stops.len(),
+            "departure": tour.stops.first().map(|stop| stop.time.departure.clone()),
+            "arrival": tour.stops.last().map(|stop| stop.time.arrival.clone()),
+            "distance": tour.statistic.distance,
+            "duration": tour.statistic.duration,
+            "cost": tour.statistic.cost,
+        })),
+        geometry: Geometry::LineString {
+            coordinates: match route {
+                Some(route) => route
+                    .coordinates
+                    .iter()
+                    .map(|location| [location.lng, location.lat])
+                    .collect(),
+                None => tour.stops.iter().map(get_stop_coordinates).collect(),
+            },
+        },
+    }
+}
+
+/// Builds a feature collection with a line for every tour followed by a point for every stop,
+/// numbered in the order the vehicle visits them.
+pub fn build_feature_collection(tours: &[RoutedTour]) -> FeatureCollection {
+    let lines = tours
+        .iter()
+        .map(|routed| get_tour_line(&routed.tour, routed.route.as_ref()));
+    let stops = tours.iter().map(|routed| &routed.tour).flat_map(|tour| {
+        tour.stops
+            .iter()
+            .enumerate()
+            .map(move |(sequence, stop)| get_stop_point(tour, sequence, stop))
+    });
+
+    FeatureCollection {
+        features: lines.chain(stops).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vrp_pragmatic::format::solution::{deserialize_solution, Solution};
+    use vrp_pragmatic::format::Location;
+
+    use std::io::BufReader;
+
+    fn get_solution() -> Solution {
+        let solution = r#"
+{
+  "statistic": {
+    "cost": 42.0,
+    "distance": 1000,
+    "duration": 600,
+    "times": { "driving": 420, "serving": 180, "waiting": 0, "break": 0 }
+  },
+  "tours": [
+    {
+      "vehicleId": "0",
+      "typeId": "0",
+      "shiftIndex": 0,
+      "stops": [
+        {
+          "location": { "lat": 51.455691, "lng": -2.586119 },
+          "time": { "arrival": "2020-06-01T09:00:00Z", "departure": "2020-06-01T09:00:00Z" },
+          "distance": 0,
+          "load": [0],
+          "activities": [{ "jobId": "departure", "type": "departure" }]
+        },
+        {
+          "location": { "lat": 51.449516, "lng": -2.57837 },
+          "time": { "arrival": "2020-06-01T09:07:00Z", "departure": "2020-06-01T09:10:00Z" },
+          "distance": 1000,
+          "load": [0],
+          "activities": [{ "jobId": "0", "type": "service" }]
+        }
+      ],
+      "statistic": {
+        "cost": 42.0,
+        "distance": 1000,
+        "duration": 600,
+        "times": { "driving": 420, "serving": 180, "waiting": 0, "break": 0 }
+      }
+    }
+  ],
+  "unassigned": []
+}"#;
+        deserialize_solution(BufReader::new(solution.as_bytes())).unwrap()
+    }
+
+    fn get_tours(routes: &[TourRoute]) -> Vec<RoutedTour> {
+        get_solution()
+            .tours
+            .into_iter()
+            .map(|tour| RoutedTour::from(tour).with_route(routes))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_feature_collection() {
+        let collection = build_feature_collection(&get_tours(&[]));
+        assert_eq!(collection.features.len(), 3);
+
+        let line = &collection.features[0];
+        assert_eq!(line.properties["vehicleId"], "0");
+        assert_eq!(line.properties["arrival"], "2020-06-01T09:07:00Z");
+        assert_eq!(
+            line.geometry,
+            Geometry::LineString {
+                coordinates: vec![[-2.586119, 51.455691], [-2.57837, 51.449516]]
+            }
+        );
+
+        let stop = &collection.features[2];
+        assert_eq!(stop.properties["sequence"], 1);
+        assert_eq!(stop.properties["arrival"], "2020-06-01T09:07:00Z");
+        assert_eq!(stop.properties["jobIds"], json!(["0"]));
+    }
+
+    #[test]
+    fn test_build_routed_feature_collection() {
+        let route = TourRoute {
+            vehicle_id: "0".to_string(),
+            shift_index: 0,
+            coordinates: vec![
+                Location {
+                    lat: 51.455691,
+                    lng: -2.586119,
+                },
+                Location {
+                    lat: 51.452,
+                    lng: -2.584,
+                },
+                Location {
+                    lat: 51.449516,
+                    lng: -2.57837,
+                },
+            ],
+            ..TourRoute::default()
+        };
+        let collection = build_feature_collection(&get_tours(&[route]));
+        match &collection.features[0].geometry {
+            Geometry::LineString { coordinates } => assert_eq!(coordinates[1], [-2.584, 51.452]),
+            geometry => panic!("Expected a line but got {:?}", geometry),
+        }
+    }
+
+    #[test]
+    fn test_serialise_feature_collection() {
+        let collection = build_feature_collection(&get_tours(&[]));
+        let json = serde_json::to_value(&collection).unwrap();
+        assert_eq!(json["type"], "FeatureCollection");
+        assert_eq!(json["features"][0]["type"], "Feature");
+        assert_eq!(json["features"][0]["geometry"]["type"], "LineString");
+        assert_eq!(json["features"][1]["geometry"]["type"], "Point");
+    }
+}
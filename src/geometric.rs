@@ -0,0 +1,367 @@
+use std::env;
+
+use async_trait::async_trait;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::Location;
+
+use crate::directions::{DirectionsFail, Route, RouteLeg, RouteProvider};
+use crate::matrix::{MatrixFail, MatrixProvider, RoutingMatrix};
+use crate::profile::TravelProfile;
+
+const EARTH_RADIUS_IN_METRES: f64 = 6_371_000.0;
+
+// WGS-84 ellipsoid
+const WGS84_SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+const WGS84_FLATTENING: f64 = 1.0 / 298.257_223_563;
+
+const VINCENTY_MAX_ITERATIONS: usize = 200;
+const VINCENTY_PRECISION: f64 = 1e-12;
+
+/// Roads rarely run in a straight line, so straight line distances are stretched by this much
+/// unless `GEOMETRIC_DETOUR_FACTOR` says otherwise.
+pub const DEFAULT_DETOUR_FACTOR: f64 = 1.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceFormula {
+    /// Great-circle distance on a spherical earth, accurate to within half a percent.
+    Haversine,
+    /// Geodesic distance on the WGS-84 ellipsoid, accurate to the millimetre but slower.
+    Vincenty,
+}
+
+impl DistanceFormula {
+    pub fn get_distance(self, from: &Location, to: &Location) -> f64 {
+        match self {
+            DistanceFormula::Haversine => get_haversine_distance(from, to),
+            DistanceFormula::Vincenty => {
+                get_vincenty_distance(from, to).unwrap_or_else(|| get_haversine_distance(from, to))
+            }
+        }
+    }
+}
+
+/// Builds matrices in process from the distances between locations, stretched by a detour factor
+/// and travelled at the profile's average speed. Needs no external service, so it's always
+/// available as a fallback, and rows are computed in parallel so that thousands of locations can
+/// be handled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeometricProvider {
+    pub formula: DistanceFormula,
+    pub detour_factor: f64,
+}
+
+impl Default for GeometricProvider {
+    fn default() -> Self {
+        GeometricProvider {
+            formula: DistanceFormula::Haversine,
+            detour_factor: DEFAULT_DETOUR_FACTOR,
+        }
+    }
+}
+
+impl GeometricProvider {
+    /// Configured through `GEOMETRIC_FORMULA` (`haversine` or `vincenty`) and
+    /// `GEOMETRIC_DETOUR_FACTOR`, either of which a request may override.
+    pub fn from_env() -> GeometricProvider {
+        let default = GeometricProvider::default();
+        let formula = env::var("GEOMETRIC_FORMULA")
+            .ok()
+            .and_then(|formula| serde_json::from_value(serde_json::Value::String(formula)).ok())
+            .unwrap_or(default.formula);
+        let detour_factor = env::var("GEOMETRIC_DETOUR_FACTOR")
+            .ok()
+            .and_then(|factor| factor.parse::<f64>().ok())
+            .filter(|factor| *factor >= 1.0)
+            .unwrap_or(default.detour_factor);
+
+        GeometricProvider {
+            formula,
+            detour_factor,
+        }
+    }
+
+    /// The provider this server is configured with, using the formula and detour factor given
+    /// instead. Detour factors below 1 would make roads shorter than the straight line between
+    /// their ends.
+    pub fn with_overrides(
+        formula: Option<DistanceFormula>,
+        detour_factor: Option<f64>,
+    ) -> Result<GeometricProvider, MatrixFail> {
+        let default = GeometricProvider::from_env();
+        let detour_factor = match detour_factor {
+            Some(factor) if !factor.is_finite() || factor < 1.0 => {
+                return Err(MatrixFail::invalid(&format!(
+                    "The detour factor has to be at least 1, not {}",
+                    factor
+                )))
+            }
+            Some(factor) => factor,
+            None => default.detour_factor,
+        };
+
+        Ok(GeometricProvider {
+            formula: formula.unwrap_or(default.formula),
+            detour_factor,
+        })
+    }
+
+    fn get_row(&self, source: &Location, destinations: &[Location]) -> Vec<f64> {
+        destinations
+            .iter()
+            .map(|destination| self.formula.get_distance(source, destination) * self.detour_factor)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl MatrixProvider for GeometricProvider {
+    async fn get_matrix(
+        &self,
+        sources: &[Location],
+        destinations: &[Location],
+        profile: TravelProfile,
+    ) -> Result<RoutingMatrix, MatrixFail> {
+        // computed on the blocking thread pool, as thousands of locations take a while
+        let provider = self.clone();
+        let (sources, destinations) = (sources.to_vec(), destinations.to_vec());
+        let rows: Vec<Vec<f64>> = tokio::task::spawn_blocking(move || {
+            sources
+                .par_iter()
+                .map(|source| provider.get_row(source, &destinations))
+                .collect()
+        })
+        .await
+        .map_err(|err| {
+            MatrixFail::new(&format!("Geometric matrix stopped unexpectedly: {}", err))
+        })?;
+
+        let speed = profile.speed();
+        Ok(RoutingMatrix {
+            durations: rows
+                .iter()
+                .map(|row| row.iter().map(|distance| Some(distance / speed)).collect())
+                .collect(),
+            distances: rows
+                .into_iter()
+                .map(|row| row.into_iter().map(Some).collect())
+                .collect(),
+        })
+    }
+}
+
+/// Draws straight lines between the waypoints, for when no routing service is available.
+#[async_trait]
+impl RouteProvider for GeometricProvider {
+    async fn get_route(
+        &self,
+        waypoints: &[Location],
+        profile: TravelProfile,
+    ) -> Result<Route, DirectionsFail> {
+        let legs = waypoints
+            .windows(2)
+            .map(|pair| {
+                let distance = self.formula.get_distance(&pair[0], &pair[1]) * self.detour_factor;
+                RouteLeg {
+                    distance,
+                    duration: distance / profile.speed(),
+                }
+            })
+            .collect();
+
+        Ok(Route {
+            coordinates: waypoints.to_vec(),
+            legs,
+        })
+    }
+}
+
+/// The great-circle distance in metres between two locations.
+pub fn get_haversine_distance(from: &Location, to: &Location) -> f64 {
+    let (from_lat, to_lat) = (from.lat.to_radians(), to.lat.to_radians());
+    let delta_lat = (to.lat - from.lat).to_radians();
+    let delta_lng = (to.lng - from.lng).to_radians();
+
+    let a = (delta_lat / 2.0).sin().powi(2)
+        + from_lat.cos() * to_lat.cos() * (delta_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_IN_METRES * a.sqrt().asin()
+}
+
+/// The geodesic distance in metres between two locations, from Vincenty's inverse formula. The
+/// formula doesn't converge for nearly antipodal points, which gives `None`.
+pub fn get_vincenty_distance(from: &Location, to: &Location) -> Option<f64> {
+    let a = WGS84_SEMI_MAJOR_AXIS;
+    let f = WGS84_FLATTENING;
+    let b = (1.0 - f) * a;
+
+    let l = (to.lng - from.lng).to_radians();
+    let u1 = ((1.0 - f) * from.lat.to_radians().tan()).atan();
+    let u2 = ((1.0 - f) * to.lat.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_MAX_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            return Some(0.0);
+        }
+
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha.powi(2);
+        // both points are on the equator
+        let cos_2_sigma_m = if cos_sq_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = f / 16.0 * cos_sq_alpha * (4.0 + f * (4.0 - 3.0 * cos_sq_alpha));
+
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * f
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2_sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2_sigma_m.powi(2))));
+
+        if (lambda - previous).abs() < VINCENTY_PRECISION {
+            let u_sq = cos_sq_alpha * (a.powi(2) - b.powi(2)) / b.powi(2);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2_sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2_sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2_sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2_sigma_m.powi(2))));
+
+            return Some(b * big_a * (sigma - delta_sigma));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_locations() -> Vec<Location> {
+        vec![
+            Location {
+                lat: 51.455691,
+                lng: -2.586119,
+            },
+            Location {
+                lat: 51.375932,
+                lng: -2.382291,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_get_haversine_distance() {
+        let locations = get_locations();
+        let distance = get_haversine_distance(&locations[0], &locations[1]);
+        assert!((distance - 16_687.0).abs() < 1.0, "{}", distance);
+        assert_eq!(get_haversine_distance(&locations[0], &locations[0]), 0.0);
+    }
+
+    #[test]
+    fn test_get_vincenty_distance() {
+        // Flinders Peak to Buninyong, the worked example from Vincenty's paper
+        let flinders_peak = Location {
+            lat: -37.951_033_42,
+            lng: 144.424_867_89,
+        };
+        let buninyong = Location {
+            lat: -37.652_821_14,
+            lng: 143.926_495_54,
+        };
+        let distance = get_vincenty_distance(&flinders_peak, &buninyong).unwrap();
+        assert!((distance - 54_972.271).abs() < 0.01, "{}", distance);
+
+        let locations = get_locations();
+        assert_eq!(
+            get_vincenty_distance(&locations[0], &locations[0]),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn test_vincenty_falls_back_for_antipodes() {
+        let from = Location { lat: 0.0, lng: 0.0 };
+        let to = Location {
+            lat: 0.5,
+            lng: 179.7,
+        };
+        assert_eq!(get_vincenty_distance(&from, &to), None);
+        assert!(DistanceFormula::Vincenty.get_distance(&from, &to) > 19_000_000.0);
+    }
+
+    #[tokio::test]
+    async fn test_get_matrix() {
+        let locations = get_locations();
+        let provider = GeometricProvider {
+            formula: DistanceFormula::Haversine,
+            detour_factor: 1.5,
+        };
+        let matrix = provider
+            .get_matrix(&locations[..1], &locations, TravelProfile::Foot)
+            .await
+            .unwrap();
+
+        assert_eq!(matrix.durations.len(), 1);
+        assert_eq!(matrix.durations[0][0], Some(0.0));
+        let distance = matrix.distances[0][1].unwrap();
+        assert_eq!(
+            distance,
+            get_haversine_distance(&locations[0], &locations[1]) * 1.5
+        );
+        assert_eq!(matrix.durations[0][1], Some(distance / 1.4));
+    }
+
+    #[test]
+    fn test_with_overrides() {
+        let provider =
+            GeometricProvider::with_overrides(Some(DistanceFormula::Vincenty), Some(1.1)).unwrap();
+        assert_eq!(provider.formula, DistanceFormula::Vincenty);
+        assert_eq!(provider.detour_factor, 1.1);
+
+        assert_eq!(
+            GeometricProvider::with_overrides(None, None).unwrap(),
+            GeometricProvider::from_env()
+        );
+        assert!(GeometricProvider::with_overrides(None, Some(0.5)).is_err());
+        assert!(GeometricProvider::with_overrides(None, Some(f64::NAN)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_large_matrix() {
+        let locations: Vec<Location> = (0..2000)
+            .map(|index| Location {
+                lat: 50.0 + (index / 50) as f64 * 0.05,
+                lng: -5.0 + (index % 50) as f64 * 0.1,
+            })
+            .collect();
+        let matrix = GeometricProvider::default()
+            .get_matrix(&locations, &locations, TravelProfile::Car)
+            .await
+            .unwrap();
+
+        assert_eq!(matrix.distances.len(), 2000);
+        assert!(matrix.distances.iter().all(|row| row.len() == 2000));
+    }
+}
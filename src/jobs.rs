@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::export::fmt;
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::problem::{Matrix, Problem};
+use vrp_pragmatic::format::solution::Solution;
+use vrp_pragmatic::format::FormatError;
+use warp::http::StatusCode;
+use warp::{reject, Rejection, Reply};
+
+use crate::directions;
+use crate::feasibility;
+use crate::feasibility::Violation;
+use crate::geocoding::UnresolvedLocation;
+use crate::profile::TravelProfile;
+use crate::redis_manager;
+use crate::request::SolveOptions;
+use crate::response::{ErrorResponse, SolveResponse, ViolationsResponse};
+use crate::solver;
+use crate::solver::{SolveError, SolverConfig, DEFAULT_MAX_TIME_SECONDS};
+use crate::user::get_uid_from_token;
+
+pub const SOLVER_JOBS_TABLE_NAME: &str = "SOLVER_JOBS";
+pub const SOLVER_RESULTS_TABLE_NAME: &str = "SOLVER_RESULTS";
+const SOLVER_JOB_ID_KEY: &str = "SOLVER_JOB_ID";
+/// How long jobs and their solutions are kept around to be polled after being queued.
+const SOLVER_JOB_TTL_SECONDS: usize = 60 * 60 * 24;
+/// How long a job may carry on past its time limit before it's taken to have been lost, such as
+/// to the server restarting while it was solving.
+const STALE_JOB_GRACE_SECONDS: i64 = 5 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolverJob {
+    pub id: String,
+    pub owner: String,
+    pub status: JobStatus,
+    pub queued_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub solver_config: SolverConfig,
+}
+
+impl fmt::Display for SolverJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ID: {} Owner: {} Status: {:?}",
+            self.id, self.owner, self.status
+        )
+    }
+}
+
+impl SolverJob {
+    pub fn new(id: String, owner: String, solver_config: SolverConfig) -> SolverJob {
+        SolverJob {
+            id,
+            owner,
+            solver_config,
+            status: JobStatus::Queued,
+            queued_at: Utc::now().to_rfc3339(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+        }
+    }
+
+    fn start(self) -> SolverJob {
+        SolverJob {
+            status: JobStatus::Running,
+            started_at: Some(Utc::now().to_rfc3339()),
+            ..self
+        }
+    }
+
+    fn finish(self) -> SolverJob {
+        SolverJob {
+            status: JobStatus::Finished,
+            finished_at: Some(Utc::now().to_rfc3339()),
+            ..self
+        }
+    }
+
+    fn fail(self, error: String) -> SolverJob {
+        SolverJob {
+            status: JobStatus::Failed,
+            finished_at: Some(Utc::now().to_rfc3339()),
+            error: Some(error),
+            ..self
+        }
+    }
+
+    /// Whether the job is still queued or running at `now` well after it should have finished.
+    fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        let since = match self.status {
+            JobStatus::Queued => &self.queued_at,
+            JobStatus::Running => self.started_at.as_ref().unwrap_or(&self.queued_at),
+            JobStatus::Finished | JobStatus::Failed => return false,
+        };
+        let max_time = self
+            .solver_config
+            .max_time
+            .unwrap_or(DEFAULT_MAX_TIME_SECONDS) as i64;
+
+        DateTime::parse_from_rfc3339(since).is_ok_and(|since| {
+            now.signed_duration_since(since) > Duration::seconds(max_time + STALE_JOB_GRACE_SECONDS)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SolverJobResult {
+    id: String,
+    solution: Solution,
+    #[serde(default)]
+    violations: Vec<Violation>,
+    /// The travel profile of every vehicle type, so that tours can be routed later on.
+    #[serde(default)]
+    profiles: HashMap<String, TravelProfile>,
+    #[serde(default)]
+    unresolved: Vec<UnresolvedLocation>,
+}
+
+impl fmt::Display for SolverJobResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Solution for job {} with {} tours",
+            self.id,
+            self.solution.tours.len()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct JobFail {
+    message: String,
+}
+
+impl reject::Reject for JobFail {}
+
+impl JobFail {
+    pub fn new(message: &str) -> JobFail {
+        JobFail {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn to_reply(&self) -> warp::reply::Response {
+        ErrorResponse::new("JOB_UNAVAILABLE", &self.message)
+            .into_reply(StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+fn build_key(table: &str, id: &str) -> String {
+    format!("{}:{}", table, id)
+}
+
+fn save(job: &SolverJob) -> Option<String> {
+    redis_manager::set_with_expiry(
+        &build_key(SOLVER_JOBS_TABLE_NAME, &job.id),
+        job.clone(),
+        SOLVER_JOB_TTL_SECONDS,
+    )
+}
+
+/// Gets a job, failing it first if it was lost while queued or running.
+pub fn get_job(id: &str) -> Option<SolverJob> {
+    let job = redis_manager::get_value::<SolverJob>(&build_key(SOLVER_JOBS_TABLE_NAME, id))?;
+    if !job.is_stale(Utc::now()) {
+        return Some(job);
+    }
+
+    let failed = job.fail("The solver job stopped without finishing".to_string());
+    log::warn!("Solver job went stale: {}", failed);
+    save(&failed);
+    Some(failed)
+}
+
+fn get_job_result(id: &str) -> Option<SolverJobResult> {
+    redis_manager::get_value::<SolverJobResult>(&build_key(SOLVER_RESULTS_TABLE_NAME, id))
+}
+
+/// Stores a queued job in redis and solves it on the blocking thread pool, recording every state
+/// change so that the job can be polled after the enqueuing connection has gone away.
+pub fn enqueue(
+    owner: String,
+    problem: Problem,
+    matrices: Option<Vec<Matrix>>,
+    config: SolverConfig,
+    unresolved: Vec<UnresolvedLocation>,
+) -> Option<SolverJob> {
+    let id = redis_manager::increment(SOLVER_JOB_ID_KEY)?.to_string();
+    let job = SolverJob::new(id, owner, config);
+    save(&job)?;
+
+    let profiles = directions::get_vehicle_profiles(&problem);
+    let queued = job.clone();
+    tokio::task::spawn(async move {
+        let running = queued.start();
+        save(&running);
+
+        let id = running.id.clone();
+        let config = running.solver_config.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            solver::solve(problem, matrices, &config)
+                .map(|context| (feasibility::get_violations(&context), context.solution))
+        })
+        .await;
+
+        let done = match result {
+            Ok(Ok((violations, solution))) => {
+                let result = SolverJobResult {
+                    id: id.clone(),
+                    solution,
+                    violations,
+                    profiles,
+                    unresolved,
+                };
+                let key = build_key(SOLVER_RESULTS_TABLE_NAME, &id);
+                match redis_manager::set_with_expiry(&key, result, SOLVER_JOB_TTL_SECONDS) {
+                    Some(_) => running.finish(),
+                    None => running.fail("Unable to store the solution".to_string()),
+                }
+            }
+            Ok(Err(SolveError::Problem(errors))) => {
+                running.fail(FormatError::format_many(&errors, ", "))
+            }
+            Ok(Err(SolveError::Solver(fail))) => running.fail(fail.message().to_string()),
+            Err(err) => running.fail(format!("Solver stopped unexpectedly: {}", err)),
+        };
+        log::info!("Solver job finished: {}", done);
+        save(&done);
+    });
+
+    Some(job)
+}
+
+async fn get_owned_job(id: String, token: String) -> Result<SolverJob, Rejection> {
+    let uid = get_uid_from_token(token).await?;
+
+    match get_job(&id) {
+        Some(job) if job.owner == uid => Ok(job),
+        _ => Err(reject::not_found()),
+    }
+}
+
+pub async fn receive_and_get_job(id: String, token: String) -> Result<impl Reply, Rejection> {
+    let job = get_owned_job(id, token).await?;
+    Ok(warp::reply::json(&job))
+}
+
+pub async fn receive_and_get_job_result(
+    id: String,
+    token: String,
+    accept: Option<String>,
+    options: SolveOptions,
+) -> Result<impl Reply, Rejection> {
+    let job = get_owned_job(id, token).await?;
+
+    let response = match job.status {
+        JobStatus::Finished => {
+            let result = get_job_result(&job.id)
+                .ok_or_else(|| reject::custom(JobFail::new("Unable to find the solution")))?;
+            if options.is_strict() && !result.violations.is_empty() {
+                ViolationsResponse::new(result.violations).into_reply()
+            } else {
+                let routes = if options.wants_directions() {
+                    let source = options.matrix_provider().map_err(reject::custom)?;
+                    directions::route_tours(&source, &result.solution, &result.profiles)
+                        .await
+                        .map_err(reject::custom)?
+                } else {
+                    vec![]
+                };
+                SolveResponse::new(result.solution, job.solver_config)
+                    .with_warnings(result.violations)
+                    .with_routes(routes)
+                    .with_unresolved(result.unresolved)
+                    .into_reply(options.response_format(accept.as_deref()))
+            }
+        }
+        JobStatus::Failed => {
+            warp::reply::with_status(warp::reply::json(&job), StatusCode::UNPROCESSABLE_ENTITY)
+                .into_response()
+        }
+        JobStatus::Queued | JobStatus::Running => {
+            warp::reply::with_status(warp::reply::json(&job), StatusCode::ACCEPTED).into_response()
+        }
+    };
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let job = SolverJob::new(
+            "1".to_string(),
+            "owner".to_string(),
+            SolverConfig::default(),
+        );
+        assert_eq!(job.status, JobStatus::Queued);
+        assert!(job.started_at.is_none());
+
+        let job = job.start();
+        assert_eq!(job.status, JobStatus::Running);
+        assert!(job.started_at.is_some());
+        assert!(job.finished_at.is_none());
+
+        let finished = job.clone().finish();
+        assert_eq!(finished.status, JobStatus::Finished);
+        assert!(finished.finished_at.is_some());
+
+        let failed = job.fail("reason".to_string());
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error, Some("reason".to_string()));
+    }
+
+    #[test]
+    fn test_stale_job() {
+        let config = SolverConfig {
+            max_time: Some(60),
+            ..SolverConfig::default()
+        };
+        let job = SolverJob::new("1".to_string(), "owner".to_string(), config).start();
+        let now = Utc::now();
+        assert!(!job.is_stale(now));
+        assert!(!job.is_stale(now + Duration::seconds(60)));
+        assert!(job.is_stale(now + Duration::seconds(60 + STALE_JOB_GRACE_SECONDS + 1)));
+
+        let finished = job.finish();
+        assert!(!finished.is_stale(now + Duration::days(1)));
+    }
+
+    #[test]
+    fn test_serialise_job() {
+        let job = SolverJob::new(
+            "1".to_string(),
+            "owner".to_string(),
+            SolverConfig::default(),
+        );
+        let json = serde_json::to_value(&job).unwrap();
+        assert_eq!(json["status"], "queued");
+        assert_eq!(json["id"], "1");
+        assert!(json.get("queuedAt").is_some());
+        assert!(json.get("error").is_none());
+    }
+
+    #[test]
+    fn test_save_and_get_job() {
+        let job = SolverJob::new(
+            "TEST_JOB".to_string(),
+            "owner".to_string(),
+            SolverConfig::default(),
+        );
+        save(&job).unwrap();
+        assert_eq!(get_job("TEST_JOB"), Some(job));
+    }
+}
@@ -1,33 +1,57 @@
-#[macro_use]
-extern crate cached;
-
-use std::convert::Infallible;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
 
 use vrp_pragmatic::checker::CheckerContext;
-use vrp_pragmatic::format::problem::{Matrix, PragmaticProblem, Problem};
+use vrp_pragmatic::format::problem::{Matrix, Problem};
+use vrp_pragmatic::format::FormatError;
 
-use warp::http::Method;
+use warp::http::{Method, StatusCode};
+use warp::reply::Response;
 
-use warp::{Filter, Rejection};
+use warp::{reject, Filter, Rejection};
 
-use crate::user::get_user_from_token;
+use crate::directions::DirectionsFail;
+use crate::geocoding::{GeocodingFail, UnresolvedLocation};
+use crate::jobs::JobFail;
+use crate::matrix::MatrixFail;
+use crate::matrix_cache::CacheStats;
+use crate::profile::TravelProfile;
+use crate::response::{ErrorResponse, MatrixResponse, SolveResponse, ViolationsResponse};
+use crate::solver::{SolveError, SolverConfig, SolverConfigFail, SolverFail};
+use crate::user::{get_user, get_verified_user, UserFail};
 
 pub mod auth;
+mod directions;
+mod feasibility;
 pub mod geocoding;
+mod geojson;
+mod geometric;
+mod jobs;
 mod mapbox;
+mod matrix;
+mod matrix_cache;
+mod memory_geocoder;
+mod osrm_service;
+mod postcode;
+mod profile;
 mod redis_manager;
 mod request;
+mod response;
 mod solver;
+mod spatial;
+mod traffic;
 pub mod user;
 
 pub async fn start_server(addr: SocketAddr) {
     tokio::task::spawn(async {
         geocoding::get_postcodes();
     });
+    tokio::task::spawn_blocking(|| {
+        geocoding::get_memory_geocoder();
+    });
 
     const AUTH_HEADER: &str = "authorization";
+    const ACCEPT_HEADER: &str = "accept";
 
     let cors = warp::cors()
         .allow_methods(&[Method::GET, Method::POST])
@@ -54,11 +78,33 @@ pub async fn start_server(addr: SocketAddr) {
         .and(warp::header::<String>(AUTH_HEADER))
         .and_then(geocoding::receive_and_search_postcode);
 
+    let nearby_postcodes = warp::path!("geocoding" / "nearby" / f64 / f64)
+        .and(warp::get())
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and(warp::query::<geocoding::NearbyQuery>())
+        .and_then(geocoding::receive_and_search_nearby_postcodes);
+
+    let batch_geocoding = warp::path!("geocoding" / "batch")
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<geocoding::BatchGeocoding>())
+        .and_then(geocoding::receive_and_batch_search);
+
+    let geocoding = warp::path!("geocoding")
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::json::<geocoding::Geocoding>())
+        .and_then(geocoding::receive_and_geocode);
+
     let simple_trip = warp::path!("routing" / "solver" / "simple")
         .and(warp::header::<String>(AUTH_HEADER))
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 16))
         .and(warp::body::json::<request::SimpleTrip>())
+        .and(warp::header::optional::<String>(ACCEPT_HEADER))
+        .and(warp::query::<request::SolveOptions>())
         .and_then(simple_trip);
 
     let simple_trip_matrix = warp::path!("routing" / "solver" / "simple" / "matrix")
@@ -66,6 +112,8 @@ pub async fn start_server(addr: SocketAddr) {
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 16))
         .and(warp::body::json::<request::SimpleTrip>())
+        .and(warp::header::optional::<String>(ACCEPT_HEADER))
+        .and(warp::query::<request::SolveOptions>())
         .and_then(simple_trip_matrix);
 
     let simple_trip_async = warp::path!("routing" / "solver" / "simple" / "async")
@@ -75,10 +123,42 @@ pub async fn start_server(addr: SocketAddr) {
         .and(warp::body::json::<request::SimpleTrip>())
         .and_then(simple_trip_async);
 
+    let detailed_trip = warp::path!("routing" / "solver" / "detailed")
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<request::DetailedRequest>())
+        .and(warp::header::optional::<String>(ACCEPT_HEADER))
+        .and(warp::query::<request::SolveOptions>())
+        .and_then(detailed_trip);
+
+    let solver_job = warp::path!("routing" / "solver" / "jobs" / String)
+        .and(warp::get())
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and_then(jobs::receive_and_get_job);
+
+    let solver_job_result = warp::path!("routing" / "solver" / "jobs" / String / "result")
+        .and(warp::get())
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and(warp::header::optional::<String>(ACCEPT_HEADER))
+        .and(warp::query::<request::SolveOptions>())
+        .and_then(jobs::receive_and_get_job_result);
+
+    let routing_matrix = warp::path!("routing" / "matrix")
+        .and(warp::header::<String>(AUTH_HEADER))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 64))
+        .and(warp::body::json::<request::MatrixRequest>())
+        .and(warp::query::<request::SolveOptions>())
+        .and_then(routing_matrix);
+
     let trip = warp::path!("routing" / "solver")
         .and(warp::header::<String>(AUTH_HEADER))
-        .and(warp::body::content_length_limit(1024 * 16))
-        .and(warp::body::json())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<request::PragmaticTrip>())
+        .and(warp::header::optional::<String>(ACCEPT_HEADER))
+        .and(warp::query::<request::SolveOptions>())
         .and_then(trip);
 
     let routes = trip
@@ -87,8 +167,16 @@ pub async fn start_server(addr: SocketAddr) {
         .or(simple_trip)
         .or(simple_trip_matrix)
         .or(simple_trip_async)
+        .or(detailed_trip)
+        .or(solver_job)
+        .or(solver_job_result)
+        .or(routing_matrix)
         .or(forward_geocoding)
         .or(reverse_geocoding)
+        .or(nearby_postcodes)
+        .or(batch_geocoding)
+        .or(geocoding)
+        .recover(handle_rejection)
         // TODO [#19]: fix compression .with(warp::compression::gzip())
         // .with(warp::compression::gzip())
         .with(&cors);
@@ -97,123 +185,261 @@ pub async fn start_server(addr: SocketAddr) {
     warp::serve(routes).run(addr).await;
 }
 
-pub async fn trip(token: String, _request: Problem) -> Result<impl warp::Reply, Infallible> {
-    get_user_from_token(token).await.unwrap();
-    Ok("result")
+pub async fn trip(
+    token: String,
+    trip: request::PragmaticTrip,
+    accept: Option<String>,
+    options: request::SolveOptions,
+) -> Result<impl warp::Reply, Rejection> {
+    let (_, config) = get_solver_config(token, trip.solver_config.as_ref()).await?;
+    let profiles = directions::get_vehicle_profiles(&trip.problem);
+
+    let context = solve(trip.problem, trip.matrices, &config).await?;
+
+    reply_with_solution(context, config, &options, accept, None, &profiles, vec![]).await
 }
 
 pub async fn simple_trip(
     token: String,
     trip: request::SimpleTrip,
-) -> Result<impl warp::Reply, Infallible> {
-    get_user_from_token(token).await.unwrap();
+    accept: Option<String>,
+    options: request::SolveOptions,
+) -> Result<impl warp::Reply, Rejection> {
+    trip.check_without_traffic().map_err(reject::custom)?;
+    let (_, config) = get_solver_config(token, trip.solver_config.as_ref()).await?;
     // TODO [#29]: add some concurrency here
     // Convert simple trip to internal problem
-    let problem = trip.clone().convert_to_internal_problem().await;
-    // Convert internal problem to a core problem
-    let core_problem = problem.read_pragmatic();
-    // Create an ARC for it
-    let problem =
-        Arc::new(core_problem.expect("Could not read a pragmatic problem into a core problem"));
-    // Start building a solution
-    let (solution, _, _) = solver::solve_problem(solver::create_solver(problem.clone()));
-    // Convert that to a pragmatic solution
-    let (solution, _) =
-        solver::get_pragmatic_solution(&Arc::try_unwrap(problem).ok().unwrap(), &solution);
-
-    // TODO [#20]: this context builder is silly, refactor it
-    let problem: Problem = trip.convert_to_internal_problem().await;
-    let context = CheckerContext::new(problem, None, solution);
-
-    if let Err(err) = context.check() {
-        format!("unfeasible solution in '{}': '{}'", "name", err);
-    }
+    let (problem, unresolved) = trip.convert_to_internal_problem().await?;
+    let profiles = directions::get_vehicle_profiles(&problem);
 
-    Ok(warp::reply::json(&context.solution))
-}
+    let context = solve(problem, None, &config).await?;
 
-fn get_core_problem(
-    problem: Problem,
-    matrices: Option<Vec<Matrix>>,
-) -> Arc<vrp_core::models::Problem> {
-    Arc::new(
-        if let Some(matrices) = matrices {
-            (problem, matrices).read_pragmatic()
-        } else {
-            problem.read_pragmatic()
-        }
-        .ok()
-        .unwrap(),
+    reply_with_solution(
+        context, config, &options, accept, None, &profiles, unresolved,
     )
+    .await
 }
 
-pub async fn simple_trip_matrix(
+pub async fn detailed_trip(
     token: String,
-    trip: request::SimpleTrip,
+    trip: request::DetailedRequest,
+    accept: Option<String>,
+    options: request::SolveOptions,
 ) -> Result<impl warp::Reply, Rejection> {
-    get_user_from_token(token).await.unwrap();
-    if let Err(err) = apply_mapbox_max_jobs(&trip) {
-        return Err(err);
-    }
+    let (_, config) = get_solver_config(token, trip.solver_config.as_ref()).await?;
 
-    let problem = trip.clone().convert_to_internal_problem().await;
+    let problem = trip.convert_to_internal_problem()?;
+    let profiles = directions::get_vehicle_profiles(&problem);
 
-    let matrix = build_matrix(&trip).await;
-    let matrix_copy = matrix.clone();
+    let context = solve(problem, None, &config).await?;
 
-    let problem = get_core_problem(problem, Some(vec![matrix]));
+    reply_with_solution(context, config, &options, accept, None, &profiles, vec![]).await
+}
 
-    let (solution, _, _) = solver::solve_problem(solver::create_solver(problem.clone()));
+#[derive(Debug)]
+pub struct ProblemFail {
+    message: String,
+}
 
-    let (solution, _) =
-        solver::get_pragmatic_solution(&Arc::try_unwrap(problem).ok().unwrap(), &solution);
+impl reject::Reject for ProblemFail {}
 
-    let problem: Problem = trip.convert_to_internal_problem().await;
+impl ProblemFail {
+    pub fn new(errors: Vec<FormatError>) -> ProblemFail {
+        ProblemFail {
+            message: format!(
+                "Unable to read the pragmatic problem: {}",
+                FormatError::format_many(&errors, ", ")
+            ),
+        }
+    }
+
+    pub fn invalid(message: &str) -> ProblemFail {
+        ProblemFail {
+            message: message.to_string(),
+        }
+    }
 
-    let context = CheckerContext::new(problem, Some(vec![matrix_copy]), solution);
-    if let Err(err) = context.check() {
-        format!("unfeasible solution in '{}': '{}'", "name", err);
+    pub fn to_reply(&self) -> Response {
+        ErrorResponse::new("INVALID_PROBLEM", &self.message).into_reply(StatusCode::BAD_REQUEST)
     }
+}
 
-    Ok(warp::reply::json(&context.solution))
+/// Replies to every failure of this service with its status and a JSON body giving its code and
+/// message, passing any other rejection on to warp untouched.
+async fn handle_rejection(rejection: Rejection) -> Result<Response, Rejection> {
+    rejection
+        .find::<GeocodingFail>()
+        .map(GeocodingFail::to_reply)
+        .or_else(|| rejection.find::<ProblemFail>().map(ProblemFail::to_reply))
+        .or_else(|| rejection.find::<SolverFail>().map(SolverFail::to_reply))
+        .or_else(|| {
+            rejection
+                .find::<SolverConfigFail>()
+                .map(SolverConfigFail::to_reply)
+        })
+        .or_else(|| rejection.find::<JobFail>().map(JobFail::to_reply))
+        .or_else(|| rejection.find::<MatrixFail>().map(MatrixFail::to_reply))
+        .or_else(|| {
+            rejection
+                .find::<DirectionsFail>()
+                .map(DirectionsFail::to_reply)
+        })
+        .or_else(|| rejection.find::<UserFail>().map(UserFail::to_reply))
+        .ok_or(rejection)
 }
 
-fn apply_mapbox_max_jobs(trip: &request::SimpleTrip) -> std::result::Result<(), Rejection> {
-    if trip.coordinate_jobs.len() + trip.coordinate_vehicles.len() >= 25 {
-        Err(warp::reject::reject())
-    } else {
-        Ok(())
+/// Solves a pragmatic problem on the blocking thread pool, so that long solves don't hold up the
+/// executor, rejecting the request when the problem can't be read.
+async fn solve(
+    problem: Problem,
+    matrices: Option<Vec<Matrix>>,
+    config: &SolverConfig,
+) -> Result<CheckerContext, Rejection> {
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || solver::solve(problem, matrices, &config))
+        .await
+        .map_err(|err| {
+            reject::custom(SolverFail::new(&format!(
+                "Solver stopped unexpectedly: {}",
+                err
+            )))
+        })?
+        .map_err(|err| match err {
+            SolveError::Problem(errors) => reject::custom(ProblemFail::new(errors)),
+            SolveError::Solver(fail) => reject::custom(fail),
+        })
+}
+
+/// Replies with the solution in the negotiated format, or with its violations when the solution
+/// is unfeasible and the request is strict. Tours are routed along the roads with the travel
+/// profiles of their vehicle types when directions are asked for, and any locations dropped from
+/// the problem are reported with it.
+async fn reply_with_solution(
+    context: CheckerContext,
+    config: SolverConfig,
+    options: &request::SolveOptions,
+    accept: Option<String>,
+    matrix_cache: Option<CacheStats>,
+    profiles: &HashMap<String, TravelProfile>,
+    unresolved: Vec<UnresolvedLocation>,
+) -> Result<Response, Rejection> {
+    let violations = feasibility::get_violations(&context);
+    if options.is_strict() && !violations.is_empty() {
+        return Ok(ViolationsResponse::new(violations).into_reply());
     }
+
+    let routes = if options.wants_directions() {
+        let source = options.matrix_provider().map_err(reject::custom)?;
+        directions::route_tours(&source, &context.solution, profiles)
+            .await
+            .map_err(reject::custom)?
+    } else {
+        vec![]
+    };
+
+    Ok(SolveResponse::new(context.solution, config)
+        .with_warnings(violations)
+        .with_matrix_cache(matrix_cache)
+        .with_routes(routes)
+        .with_unresolved(unresolved)
+        .into_reply(options.response_format(accept.as_deref())))
 }
 
-async fn build_matrix(trip: &request::SimpleTrip) -> Matrix {
-    let matrix_vehicles: Vec<Vec<f64>> = trip
-        .clone()
-        .coordinate_vehicles
-        .iter()
-        .map(|coordinate| geocoding::lookup_coordinates(String::from(coordinate)))
-        .map(|location| vec![location.lng, location.lat])
-        .collect();
-    let matrix_jobs: Vec<Vec<f64>> = trip
-        .clone()
-        .coordinate_jobs
-        .iter()
-        .map(|coordinate| geocoding::lookup_coordinates(String::from(coordinate)))
-        .map(|location| vec![location.lng, location.lat])
-        .collect();
-    let concat = [&matrix_jobs[..], &matrix_vehicles[..]].concat();
-
-    let internal_matrix = mapbox::get_matrix(concat).await.unwrap_or_default();
-
-    mapbox::convert_to_vrp_matrix(internal_matrix).await
+/// Resolves the requested solver config against the caps of the user behind the token, along
+/// with the verified uid of the user.
+async fn get_solver_config(
+    token: String,
+    config: Option<&SolverConfig>,
+) -> Result<(String, SolverConfig), Rejection> {
+    let (uid, _) = get_verified_user(token).await?;
+    let config = config
+        .cloned()
+        .unwrap_or_default()
+        .resolve(&user::get_solver_limits(&uid))
+        .map_err(reject::custom)?;
+    Ok((uid, config))
+}
+
+pub async fn simple_trip_matrix(
+    token: String,
+    trip: request::SimpleTrip,
+    accept: Option<String>,
+    options: request::SolveOptions,
+) -> Result<impl warp::Reply, Rejection> {
+    let (_, config) = get_solver_config(token, trip.solver_config.as_ref()).await?;
+
+    let (problem, unresolved) = trip.convert_to_internal_problem().await?;
+    let profiles = directions::get_vehicle_profiles(&problem);
+
+    let source = options.matrix_provider().map_err(reject::custom)?;
+    let (matrices, matrix_cache) = matrix::build_cached_matrices(
+        &source,
+        &problem,
+        trip.get_profiles(),
+        trip.traffic.as_ref(),
+    )
+    .await
+    .map_err(reject::custom)?;
+
+    let context = solve(problem, Some(matrices), &config).await?;
+
+    reply_with_solution(
+        context,
+        config,
+        &options,
+        accept,
+        matrix_cache,
+        &profiles,
+        unresolved,
+    )
+    .await
+}
+
+pub async fn routing_matrix(
+    token: String,
+    request: request::MatrixRequest,
+    options: request::SolveOptions,
+) -> Result<impl warp::Reply, Rejection> {
+    get_user(token).await?;
+
+    let (origins, destinations) = request.resolve_locations().map_err(reject::custom)?;
+
+    let source = options.matrix_provider().map_err(reject::custom)?;
+    let (matrix, matrix_cache) =
+        matrix::get_cached_routing_matrix(&source, &origins, &destinations, request.profile)
+            .await
+            .map_err(reject::custom)?;
+
+    Ok(warp::reply::json(&MatrixResponse {
+        profile: request.profile,
+        origins: request
+            .origins
+            .iter()
+            .map(request::SimpleLocation::label)
+            .collect(),
+        destinations: request
+            .get_destinations()
+            .iter()
+            .map(request::SimpleLocation::label)
+            .collect(),
+        matrix,
+        matrix_cache,
+    }))
 }
 
 pub async fn simple_trip_async(
     token: String,
-    _trip: request::SimpleTrip,
+    trip: request::SimpleTrip,
 ) -> Result<impl warp::Reply, Rejection> {
-    get_user_from_token(token).await.unwrap();
-    tokio::task::spawn(async { println!("Hey, i'm gonna be another task") });
-    // let result = geocoding::search_postcode(vec![lat, lon]);
-    Ok("result")
+    trip.check_without_traffic().map_err(reject::custom)?;
+    let (uid, config) = get_solver_config(token, trip.solver_config.as_ref()).await?;
+    let (problem, unresolved) = trip.convert_to_internal_problem().await?;
+
+    let job = jobs::enqueue(uid, problem, None, config, unresolved)
+        .ok_or_else(|| reject::custom(jobs::JobFail::new("Unable to queue the solver job")))?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&job),
+        StatusCode::ACCEPTED,
+    ))
 }
@@ -1,8 +1,6 @@
 #![feature(option_result_contains)]
 #![feature(in_band_lifetimes)]
 
-#[macro_use]
-extern crate cached;
 #[macro_use]
 extern crate log;
 
@@ -10,12 +8,25 @@ use grekko::start_server;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 pub mod auth;
+pub mod directions;
+pub mod feasibility;
 pub mod geocoding;
+pub mod geojson;
+pub mod geometric;
+pub mod jobs;
 pub mod mapbox;
+pub mod matrix;
+pub mod matrix_cache;
+pub mod memory_geocoder;
 pub mod osrm_service;
+pub mod postcode;
+pub mod profile;
 pub mod redis_manager;
 pub mod request;
+pub mod response;
 pub mod solver;
+pub mod spatial;
+pub mod traffic;
 pub mod user;
 
 #[tokio::main]
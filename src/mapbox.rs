@@ -1,14 +1,30 @@
+use std::cmp::min;
 use std::env;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use vrp_pragmatic::format::problem::Matrix as VrpMatrix;
+use vrp_pragmatic::format::Location;
+use warp::http::{header, HeaderMap, StatusCode};
+
+use crate::directions::{DirectionsFail, DirectionsResponse, Route, RouteProvider};
+use crate::matrix;
+use crate::matrix::{MatrixFail, MatrixProvider, RoutingMatrix};
+use crate::profile::TravelProfile;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Matrix {
     pub code: String,
-    pub distances: Vec<Vec<f64>>,
-    pub durations: Vec<Vec<f64>>,
+    /// Rows of metres from each source to every destination, null where no route was found.
+    pub distances: Vec<Vec<Option<f64>>>,
+    /// Rows of seconds from each source to every destination, null where no route was found.
+    pub durations: Vec<Vec<Option<f64>>>,
     pub destinations: Vec<Destination>,
     pub sources: Vec<Source>,
 }
@@ -29,58 +45,364 @@ pub struct Source {
     pub location: Vec<f64>,
 }
 
-pub async fn get_matrix(coordinates: Vec<Vec<f64>>) -> Option<Matrix> {
-    let coords: String = coordinates
-        .iter()
-        .map(|long_lat| {
-            let coords_cols_str: Vec<String> = long_lat.iter().map(ToString::to_string).collect();
-            coords_cols_str.join(",")
-        })
-        .collect::<Vec<String>>()
-        .join(";");
+/// The most coordinates the Mapbox Matrix API accepts in a single request.
+const MAX_COORDINATES: usize = 25;
+/// The most coordinates the Mapbox Matrix API accepts in a single request with live traffic.
+const MAX_TRAFFIC_COORDINATES: usize = 10;
+const TRAFFIC_ROUTING_PROFILE: &str = "driving-traffic";
+const DEPART_AT_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+/// The most waypoints the Mapbox Directions API accepts in a single request.
+const MAX_WAYPOINTS: usize = 25;
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF_MILLIS: u64 = 500;
+
+/// Spaces requests to one of the Mapbox APIs out evenly, so that together they stay within its
+/// per minute limit however many trips are being routed at once.
+struct RateLimit {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimit {
+    const fn per_minute(requests: u64) -> RateLimit {
+        RateLimit {
+            interval: Duration::from_millis(60_000 / requests),
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Takes the next free slot, returning how long to wait until it comes.
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap_or_else(|err| err.into_inner());
+        let slot = next.map_or(now, |next| next.max(now));
+        *next = Some(slot + self.interval);
+        slot - now
+    }
+
+    async fn wait(&self) {
+        let wait = self.reserve();
+        if wait > Duration::from_millis(0) {
+            tokio::time::delay_for(wait).await;
+        }
+    }
+}
+
+/// The Matrix API allows 60 requests a minute.
+static MATRIX_RATE_LIMIT: RateLimit = RateLimit::per_minute(60);
+/// The Directions API allows 300 requests a minute.
+static DIRECTIONS_RATE_LIMIT: RateLimit = RateLimit::per_minute(300);
+
+#[derive(Debug, PartialEq)]
+enum RequestFail {
+    /// Rate limited or a temporary server error, worth trying again after a while, or after as
+    /// many seconds as Mapbox asked for with `Retry-After`.
+    Retry(Option<Duration>),
+    Fail,
+}
 
+fn get_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers.get(header::RETRY_AFTER)?.to_str().ok()?;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
+
+async fn request<T: DeserializeOwned>(
+    url: &str,
+    query: &[(String, String)],
+) -> Result<T, RequestFail> {
     let access_token = env::var("MAPBOX_ACCESS_KEY").expect("MAPBOX_ACCESS_KEY isn't set");
 
     let client = reqwest::Client::new();
-
-    println!("{}", coords);
-    let url = format!(
-        "https://api.mapbox.com/directions-matrix/v1/mapbox/driving/{}",
-        coords.as_str()
-    );
-    let response_body = client
-        .get(&url)
-        .query(&[
-            ("access_token", access_token.as_str()),
-            ("annotations", "distance,duration"),
-        ])
+    let response = client
+        .get(url)
+        .query(query)
+        .query(&[("access_token", access_token)])
         .send()
         .await
-        .ok()?
+        .map_err(|_| RequestFail::Retry(None))?;
+
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        log::warn!("Mapbox request was refused: {}", status);
+        return Err(RequestFail::Retry(get_retry_after(response.headers())));
+    }
+
+    let response_body = response
         .text()
         .await
-        .ok()?;
+        .map_err(|_| RequestFail::Retry(None))?;
+    serde_json::from_str(response_body.as_str()).map_err(|_| RequestFail::Fail)
+}
+
+fn get_backoff(attempt: u32) -> Duration {
+    Duration::from_millis(INITIAL_BACKOFF_MILLIS * 2u64.pow(attempt))
+}
 
-    let matrix: Matrix = serde_json::from_str(response_body.as_str()).ok()?;
-    println!("{:#?}", matrix);
+/// Requests `url` within the rate limit of its API, backing off exponentially while Mapbox is
+/// refusing us unless it says how long to wait for.
+async fn request_with_backoff<T: DeserializeOwned>(
+    url: &str,
+    query: &[(String, String)],
+    rate_limit: &RateLimit,
+) -> Option<T> {
+    for attempt in 0..MAX_ATTEMPTS {
+        rate_limit.wait().await;
+        match request(url, query).await {
+            Ok(body) => return Some(body),
+            Err(RequestFail::Retry(retry_after)) if attempt + 1 < MAX_ATTEMPTS => {
+                tokio::time::delay_for(retry_after.unwrap_or_else(|| get_backoff(attempt))).await
+            }
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// Requests a single matrix of at most `MAX_COORDINATES` locations, in the traffic expected at
+/// `depart_at` when it's given.
+pub async fn get_matrix(
+    sources: &[Location],
+    destinations: &[Location],
+    routing_profile: &str,
+    depart_at: Option<&str>,
+) -> Option<Matrix> {
+    let (locations, mut query) = matrix::get_coordinates_query(sources, destinations);
+    query.push(("annotations".to_string(), "distance,duration".to_string()));
+    if let Some(depart_at) = depart_at {
+        query.push(("depart_at".to_string(), depart_at.to_string()));
+    }
+
+    let url = format!(
+        "https://api.mapbox.com/directions-matrix/v1/mapbox/{}/{}",
+        routing_profile,
+        matrix::format_coordinates(&locations)
+    );
+    let matrix: Matrix = request_with_backoff(&url, &query, &MATRIX_RATE_LIMIT).await?;
+    log::debug!("Mapbox matrix response: {}", matrix.code);
     Some(matrix)
 }
 
-pub async fn convert_to_vrp_matrix(internal_matrix: Matrix) -> VrpMatrix {
-    VrpMatrix {
-        profile: Some("car".to_string()),
-        timestamp: None,
-        travel_times: internal_matrix.durations[0]
-            .clone()
-            .iter()
-            .map(|val| *val as i64)
-            .collect(),
-        distances: internal_matrix.distances[0]
-            .clone()
-            .iter()
-            .map(|val| *val as i64)
-            .collect(),
-        error_codes: None,
+/// Requests the road geometry through at most `MAX_WAYPOINTS` waypoints.
+pub async fn get_directions(
+    waypoints: &[Location],
+    routing_profile: &str,
+) -> Option<DirectionsResponse> {
+    let query = vec![
+        ("geometries".to_string(), "geojson".to_string()),
+        ("overview".to_string(), "full".to_string()),
+    ];
+
+    let url = format!(
+        "https://api.mapbox.com/directions/v5/mapbox/{}/{}",
+        routing_profile,
+        matrix::format_coordinates(waypoints)
+    );
+    request_with_backoff(&url, &query, &DIRECTIONS_RATE_LIMIT).await
+}
+
+/// A part of the full matrix that fits in a single request.
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    sources: Range<usize>,
+    destinations: Range<usize>,
+}
+
+fn get_ranges(len: usize, size: usize) -> Vec<Range<usize>> {
+    (0..len)
+        .step_by(size.max(1))
+        .map(|start| start..min(start + size, len))
+        .collect()
+}
+
+/// Splits the matrix into blocks whose sources and destinations fit in a single request together.
+fn get_blocks(
+    sources: usize,
+    destinations: usize,
+    all_to_all: bool,
+    max_coordinates: usize,
+) -> Vec<Block> {
+    if (all_to_all && sources <= max_coordinates) || sources + destinations <= max_coordinates {
+        return vec![Block {
+            sources: 0..sources,
+            destinations: 0..destinations,
+        }];
+    }
+
+    let source_size = min(sources, max_coordinates / 2);
+    let destination_size = max_coordinates - source_size;
+
+    let destination_ranges = get_ranges(destinations, destination_size);
+    get_ranges(sources, source_size)
+        .into_iter()
+        .flat_map(|sources| {
+            destination_ranges.iter().map(move |destinations| Block {
+                sources: sources.clone(),
+                destinations: destinations.clone(),
+            })
+        })
+        .collect()
+}
+
+fn stitch(sources: usize, destinations: usize, blocks: Vec<(Block, Matrix)>) -> RoutingMatrix {
+    let mut stitched = RoutingMatrix {
+        durations: vec![vec![None; destinations]; sources],
+        distances: vec![vec![None; destinations]; sources],
+    };
+
+    for (block, matrix) in blocks {
+        for (row, source) in block.sources.enumerate() {
+            stitched.durations[source][block.destinations.clone()]
+                .clone_from_slice(&matrix.durations[row]);
+            stitched.distances[source][block.destinations.clone()]
+                .clone_from_slice(&matrix.distances[row]);
+        }
+    }
+
+    stitched
+}
+
+/// Builds a matrix of any size by requesting it in blocks that respect the Mapbox coordinate
+/// limit, a few at a time, and stitching them back together.
+pub async fn get_chunked_matrix(
+    sources: &[Location],
+    destinations: &[Location],
+    routing_profile: &str,
+    depart_at: Option<&str>,
+) -> Option<RoutingMatrix> {
+    let max_coordinates = if routing_profile == TRAFFIC_ROUTING_PROFILE {
+        MAX_TRAFFIC_COORDINATES
+    } else {
+        MAX_COORDINATES
+    };
+    let blocks = get_blocks(
+        sources.len(),
+        destinations.len(),
+        sources == destinations,
+        max_coordinates,
+    );
+    log::info!(
+        "Requesting a {}x{} {} matrix from Mapbox in {} blocks",
+        sources.len(),
+        destinations.len(),
+        routing_profile,
+        blocks.len()
+    );
+
+    let results: Vec<Option<(Block, Matrix)>> = stream::iter(blocks)
+        .map(|block| async move {
+            let matrix = get_matrix(
+                &sources[block.sources.clone()],
+                &destinations[block.destinations.clone()],
+                routing_profile,
+                depart_at,
+            )
+            .await
+            .filter(|matrix| {
+                matrix.code == "Ok"
+                    && matrix.durations.len() == block.sources.len()
+                    && matrix.distances.len() == block.sources.len()
+                    && matrix
+                        .durations
+                        .iter()
+                        .chain(matrix.distances.iter())
+                        .all(|row| row.len() == block.destinations.len())
+            })?;
+            Some((block, matrix))
+        })
+        .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+        .collect()
+        .await;
+
+    let blocks = results.into_iter().collect::<Option<Vec<_>>>()?;
+    Some(stitch(sources.len(), destinations.len(), blocks))
+}
+
+/// Routes through the Mapbox APIs, driving in live traffic when `traffic` is set, as it's
+/// expected to be at `depart_at` when that's given too.
+#[derive(Default)]
+pub struct MapboxProvider {
+    pub traffic: bool,
+    pub depart_at: Option<String>,
+}
+
+impl MapboxProvider {
+    /// Drives in the traffic expected at the departure time, which Mapbox wants in UTC.
+    pub fn departing_at(departure_time: &DateTime<FixedOffset>) -> MapboxProvider {
+        MapboxProvider {
+            traffic: true,
+            depart_at: Some(
+                departure_time
+                    .with_timezone(&Utc)
+                    .format(DEPART_AT_FORMAT)
+                    .to_string(),
+            ),
+        }
+    }
+
+    fn get_routing_profile(&self, profile: TravelProfile) -> &'static str {
+        if self.traffic && profile.is_motorised() {
+            TRAFFIC_ROUTING_PROFILE
+        } else {
+            profile.routing_profile()
+        }
+    }
+
+    /// Only driving in traffic depends on when the vehicle leaves.
+    fn get_depart_at(&self, profile: TravelProfile) -> Option<&str> {
+        match self.get_routing_profile(profile) {
+            TRAFFIC_ROUTING_PROFILE => self.depart_at.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl MatrixProvider for MapboxProvider {
+    async fn get_matrix(
+        &self,
+        sources: &[Location],
+        destinations: &[Location],
+        profile: TravelProfile,
+    ) -> Result<RoutingMatrix, MatrixFail> {
+        let routing_profile = self.get_routing_profile(profile);
+        let depart_at = self.get_depart_at(profile);
+        get_chunked_matrix(sources, destinations, routing_profile, depart_at)
+            .await
+            .ok_or_else(|| {
+                MatrixFail::new(&format!(
+                    "Unable to fetch the {} matrix from Mapbox",
+                    routing_profile
+                ))
+            })
+    }
+
+    fn get_routing_key(&self, profile: TravelProfile) -> String {
+        self.get_routing_profile(profile).to_string()
+    }
+}
+
+#[async_trait]
+impl RouteProvider for MapboxProvider {
+    async fn get_route(
+        &self,
+        waypoints: &[Location],
+        profile: TravelProfile,
+    ) -> Result<Route, DirectionsFail> {
+        let routing_profile = self.get_routing_profile(profile);
+        get_directions(waypoints, routing_profile)
+            .await
+            .and_then(|directions| directions.into_route(waypoints.len()))
+            .ok_or_else(|| {
+                DirectionsFail::new(&format!(
+                    "Unable to fetch {} directions from Mapbox",
+                    routing_profile
+                ))
+            })
+    }
+
+    fn max_waypoints(&self) -> usize {
+        MAX_WAYPOINTS
     }
 }
 
@@ -88,16 +410,154 @@ pub async fn convert_to_vrp_matrix(internal_matrix: Matrix) -> VrpMatrix {
 mod tests {
     use super::*;
 
+    fn get_block_matrix(block: &Block) -> Matrix {
+        let rows = block
+            .sources
+            .clone()
+            .map(|source| {
+                block
+                    .destinations
+                    .clone()
+                    .map(|destination| Some((source * 1000 + destination) as f64))
+                    .collect()
+            })
+            .collect::<Vec<Vec<Option<f64>>>>();
+        Matrix {
+            code: "Ok".to_string(),
+            durations: rows.clone(),
+            distances: rows,
+            ..Matrix::default()
+        }
+    }
+
+    #[test]
+    fn test_get_blocks_within_limit() {
+        assert_eq!(get_blocks(25, 25, true, MAX_COORDINATES).len(), 1);
+        assert_eq!(get_blocks(10, 15, false, MAX_COORDINATES).len(), 1);
+        assert_eq!(get_blocks(13, 13, false, MAX_COORDINATES).len(), 2);
+    }
+
+    #[test]
+    fn test_get_blocks() {
+        let blocks = get_blocks(150, 150, true, MAX_COORDINATES);
+        assert_eq!(blocks.len(), 13 * 12);
+        assert!(blocks
+            .iter()
+            .all(|block| block.sources.len() + block.destinations.len() <= MAX_COORDINATES));
+
+        let blocks = get_blocks(1, 100, false, MAX_COORDINATES);
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks[0].destinations, 0..24);
+        assert_eq!(blocks[4].destinations, 96..100);
+    }
+
+    #[test]
+    fn test_stitch() {
+        let blocks: Vec<(Block, Matrix)> = get_blocks(30, 40, false, MAX_COORDINATES)
+            .into_iter()
+            .map(|block| {
+                let matrix = get_block_matrix(&block);
+                (block, matrix)
+            })
+            .collect();
+
+        let matrix = stitch(30, 40, blocks);
+        assert_eq!(matrix.durations.len(), 30);
+        assert!(matrix.durations.iter().all(|row| row.len() == 40));
+        assert_eq!(matrix.durations[0][0], Some(0.0));
+        assert_eq!(matrix.durations[29][39], Some(29039.0));
+        assert_eq!(matrix.distances[12][13], Some(12013.0));
+    }
+
+    #[test]
+    fn test_get_traffic_blocks() {
+        let blocks = get_blocks(12, 12, true, MAX_TRAFFIC_COORDINATES);
+        assert_eq!(blocks.len(), 9);
+        assert!(blocks
+            .iter()
+            .all(|block| block.sources.len() + block.destinations.len() <= 10));
+    }
+
+    #[test]
+    fn test_get_routing_profile() {
+        let provider = MapboxProvider {
+            traffic: true,
+            depart_at: None,
+        };
+        assert_eq!(
+            provider.get_routing_profile(TravelProfile::Van),
+            "driving-traffic"
+        );
+        assert_eq!(provider.get_routing_profile(TravelProfile::Foot), "walking");
+        assert_eq!(
+            MapboxProvider::default().get_routing_key(TravelProfile::Car),
+            "driving"
+        );
+    }
+
+    #[test]
+    fn test_departing_at() {
+        let departure_time = DateTime::parse_from_rfc3339("2020-06-01T08:30:00+01:00").unwrap();
+        let provider = MapboxProvider::departing_at(&departure_time);
+        assert_eq!(
+            provider.get_depart_at(TravelProfile::Truck),
+            Some("2020-06-01T07:30:00Z")
+        );
+        assert_eq!(provider.get_depart_at(TravelProfile::Bicycle), None);
+        assert_eq!(
+            MapboxProvider::default().get_depart_at(TravelProfile::Car),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_backoff() {
+        assert_eq!(get_backoff(0), Duration::from_millis(500));
+        assert_eq!(get_backoff(3), Duration::from_millis(4000));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let rate_limit = RateLimit::per_minute(60);
+        assert_eq!(rate_limit.reserve(), Duration::from_millis(0));
+        let wait = rate_limit.reserve();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+        assert!(rate_limit.reserve() > Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn test_get_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(get_retry_after(&headers), None);
+        headers.insert(header::RETRY_AFTER, "12".parse().unwrap());
+        assert_eq!(get_retry_after(&headers), Some(Duration::from_secs(12)));
+        headers.insert(
+            header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(get_retry_after(&headers), None);
+    }
+
     #[tokio::test]
     async fn test_get_matrix() {
-        let code = get_matrix(vec![
-            vec![-1.080278, 53.958332],
-            vec![-2.220000, 52.192001],
-            vec![-1.308000, 51.063202],
-        ])
-        .await
-        .unwrap()
-        .code;
+        let locations = vec![
+            Location {
+                lat: 53.958332,
+                lng: -1.080278,
+            },
+            Location {
+                lat: 52.192001,
+                lng: -2.220000,
+            },
+            Location {
+                lat: 51.063202,
+                lng: -1.308000,
+            },
+        ];
+        let code = get_matrix(&locations, &locations, "driving", None)
+            .await
+            .unwrap()
+            .code;
         assert_eq!(code, "Ok")
     }
 }
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::env;
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::problem::{Matrix as VrpMatrix, Problem};
+use vrp_pragmatic::format::{CoordIndex, Location};
+use warp::http::StatusCode;
+use warp::reject;
+use warp::reply::Response;
+
+use crate::directions::RouteProvider;
+use crate::geometric::GeometricProvider;
+use crate::mapbox::MapboxProvider;
+use crate::matrix_cache::{CacheStats, CachedProvider};
+use crate::osrm_service::OsrmProvider;
+use crate::profile::TravelProfile;
+use crate::response::ErrorResponse;
+use crate::traffic::TrafficOptions;
+
+/// Travel durations in seconds and distances in metres from every source to every destination,
+/// with `None` wherever no route was found.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoutingMatrix {
+    pub durations: Vec<Vec<Option<f64>>>,
+    pub distances: Vec<Vec<Option<f64>>>,
+}
+
+/// The error code given to the solver for pairs that couldn't be routed between.
+const UNREACHABLE: i64 = 1;
+
+impl RoutingMatrix {
+    /// Flattens the matrix row-major, which matches the location order of the pragmatic problem
+    /// as long as the matrix was built in that order. Unreachable pairs are marked with an error
+    /// code, so that the solver never routes between them.
+    pub fn to_vrp_matrix(&self, profile: TravelProfile) -> VrpMatrix {
+        let durations: Vec<Option<f64>> = self.durations.concat();
+        let distances: Vec<Option<f64>> = self.distances.concat();
+
+        let error_codes: Vec<i64> = durations
+            .iter()
+            .zip(distances.iter())
+            .map(|pair| match pair {
+                (Some(_), Some(_)) => 0,
+                _ => UNREACHABLE,
+            })
+            .collect();
+
+        VrpMatrix {
+            profile: Some(profile.name().to_string()),
+            timestamp: None,
+            travel_times: durations
+                .iter()
+                .map(|val| val.unwrap_or_default().round() as i64)
+                .collect(),
+            distances: distances
+                .iter()
+                .map(|val| val.unwrap_or_default().round() as i64)
+                .collect(),
+            error_codes: if error_codes.contains(&UNREACHABLE) {
+                Some(error_codes)
+            } else {
+                None
+            },
+        }
+    }
+
+    fn has_shape(&self, sources: usize, destinations: usize) -> bool {
+        let has_rows = |rows: &Vec<Vec<Option<f64>>>| {
+            rows.len() == sources && rows.iter().all(|row| row.len() == destinations)
+        };
+        has_rows(&self.durations) && has_rows(&self.distances)
+    }
+}
+
+#[derive(Debug)]
+pub struct MatrixFail {
+    message: String,
+    /// Whether the request asked for a matrix that can't be built, rather than a routing backend
+    /// failing to build it.
+    invalid: bool,
+}
+
+impl reject::Reject for MatrixFail {}
+
+impl MatrixFail {
+    pub fn new(message: &str) -> MatrixFail {
+        MatrixFail {
+            message: message.to_string(),
+            invalid: false,
+        }
+    }
+
+    pub fn invalid(message: &str) -> MatrixFail {
+        MatrixFail {
+            invalid: true,
+            ..MatrixFail::new(message)
+        }
+    }
+
+    pub fn to_reply(&self) -> Response {
+        if self.invalid {
+            ErrorResponse::new("INVALID_MATRIX_REQUEST", &self.message)
+                .into_reply(StatusCode::BAD_REQUEST)
+        } else {
+            ErrorResponse::new("MATRIX_UNAVAILABLE", &self.message)
+                .into_reply(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// A routing backend that can build travel time and distance matrices.
+#[async_trait]
+pub trait MatrixProvider: Send + Sync {
+    /// Routes from every source to every destination with the given travel profile.
+    async fn get_matrix(
+        &self,
+        sources: &[Location],
+        destinations: &[Location],
+        profile: TravelProfile,
+    ) -> Result<RoutingMatrix, MatrixFail>;
+
+    /// Travel profiles with the same routing key are routed identically, so their matrices are
+    /// only built once.
+    fn get_routing_key(&self, profile: TravelProfile) -> String {
+        profile.name().to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatrixProviderKind {
+    Mapbox,
+    Osrm,
+    Geometric,
+}
+
+impl MatrixProviderKind {
+    /// The provider configured for this server through `MATRIX_PROVIDER`, Mapbox by default.
+    pub fn from_env() -> MatrixProviderKind {
+        env::var("MATRIX_PROVIDER")
+            .ok()
+            .and_then(|kind| serde_json::from_value(serde_json::Value::String(kind)).ok())
+            .unwrap_or(MatrixProviderKind::Mapbox)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MatrixProviderKind::Mapbox => "mapbox",
+            MatrixProviderKind::Osrm => "osrm",
+            MatrixProviderKind::Geometric => "geometric",
+        }
+    }
+}
+
+/// The routing backend of a request, along with how geometric matrices are computed when that's
+/// the backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixSource {
+    pub kind: MatrixProviderKind,
+    pub geometric: GeometricProvider,
+}
+
+impl MatrixSource {
+    pub fn new(kind: MatrixProviderKind) -> MatrixSource {
+        MatrixSource {
+            kind,
+            geometric: GeometricProvider::from_env(),
+        }
+    }
+
+    pub fn get_provider(&self) -> Box<dyn MatrixProvider> {
+        match self.kind {
+            MatrixProviderKind::Mapbox => Box::new(MapboxProvider::default()),
+            MatrixProviderKind::Osrm => Box::new(OsrmProvider::from_env()),
+            MatrixProviderKind::Geometric => Box::new(self.geometric.clone()),
+        }
+    }
+
+    /// The same backend routing in the traffic expected at the departure time where it can,
+    /// which only Mapbox does.
+    pub fn get_traffic_provider(
+        &self,
+        departure_time: &DateTime<FixedOffset>,
+    ) -> Box<dyn MatrixProvider> {
+        match self.kind {
+            MatrixProviderKind::Mapbox => Box::new(MapboxProvider::departing_at(departure_time)),
+            _ => self.get_provider(),
+        }
+    }
+
+    /// The same backend, giving road geometries instead of matrices.
+    pub fn get_route_provider(&self) -> Box<dyn RouteProvider> {
+        match self.kind {
+            MatrixProviderKind::Mapbox => Box::new(MapboxProvider::default()),
+            MatrixProviderKind::Osrm => Box::new(OsrmProvider::from_env()),
+            MatrixProviderKind::Geometric => Box::new(self.geometric.clone()),
+        }
+    }
+}
+
+/// Formats locations the way both Mapbox and OSRM expect them in their paths.
+pub fn format_coordinates(locations: &[Location]) -> String {
+    locations
+        .iter()
+        .map(|location| format!("{},{}", location.lng, location.lat))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// Lists the locations to send to a routing service, along with the `sources` and `destinations`
+/// query parameters selecting them when the matrix isn't all-to-all.
+pub fn get_coordinates_query(
+    sources: &[Location],
+    destinations: &[Location],
+) -> (Vec<Location>, Vec<(String, String)>) {
+    if sources == destinations {
+        return (sources.to_vec(), vec![]);
+    }
+
+    let join = |indexes: std::ops::Range<usize>| {
+        indexes
+            .map(|index| index.to_string())
+            .collect::<Vec<String>>()
+            .join(";")
+    };
+    let locations = [sources, destinations].concat();
+    let query = vec![
+        ("sources".to_string(), join(0..sources.len())),
+        (
+            "destinations".to_string(),
+            join(sources.len()..locations.len()),
+        ),
+    ];
+
+    (locations, query)
+}
+
+/// Builds a matrix through the provider and checks that it covers every pair.
+pub async fn get_routing_matrix(
+    provider: &dyn MatrixProvider,
+    sources: &[Location],
+    destinations: &[Location],
+    profile: TravelProfile,
+) -> Result<RoutingMatrix, MatrixFail> {
+    let matrix = provider.get_matrix(sources, destinations, profile).await?;
+
+    if matrix.has_shape(sources.len(), destinations.len()) {
+        Ok(matrix)
+    } else {
+        Err(MatrixFail::new(&format!(
+            "The {} matrix doesn't cover every location",
+            profile.name()
+        )))
+    }
+}
+
+/// Builds one matrix per travel profile, over every location of the problem in the order the
+/// solver indexes them, in the traffic of the departure time when there's one. With several
+/// departure times there is a timestamped matrix per profile and departure time instead.
+pub async fn build_matrices(
+    provider: &dyn MatrixProvider,
+    problem: &Problem,
+    profiles: Vec<TravelProfile>,
+    traffic: Option<&TrafficOptions>,
+) -> Result<Vec<VrpMatrix>, MatrixFail> {
+    if let Some(traffic) = traffic {
+        // reject bad departure times before routing anything
+        traffic.validate()?;
+    }
+
+    let locations = CoordIndex::new(problem).unique();
+
+    let mut routed: HashMap<String, RoutingMatrix> = HashMap::new();
+    let mut matrices = vec![];
+    for profile in profiles {
+        let key = provider.get_routing_key(profile);
+        if !routed.contains_key(&key) {
+            let matrix = get_routing_matrix(provider, &locations, &locations, profile).await?;
+            routed.insert(key.clone(), matrix);
+        }
+        match traffic {
+            Some(traffic) => matrices.extend(traffic.to_vrp_matrices(&routed[&key], profile)?),
+            None => matrices.push(routed[&key].to_vrp_matrix(profile)),
+        }
+    }
+
+    Ok(matrices)
+}
+
+/// Builds the matrices of the problem through the given provider. Pairs routed by a remote
+/// service are cached, along with how many of them were served from that cache, while geometric
+/// matrices are cheaper to compute than to look up and live traffic goes stale long before the
+/// cache would expire.
+///
+/// Mapbox routes a single departure time in the traffic it expects then. Several departure times,
+/// or a single one through any other provider, are built from the free flowing matrix scaled by
+/// the speed multiplier of each hour, as scaling live traffic would count the traffic twice.
+pub async fn build_cached_matrices(
+    source: &MatrixSource,
+    problem: &Problem,
+    profiles: Vec<TravelProfile>,
+    traffic: Option<&TrafficOptions>,
+) -> Result<(Vec<VrpMatrix>, Option<CacheStats>), MatrixFail> {
+    if let Some(traffic) = traffic {
+        traffic.validate()?;
+        if !traffic.is_time_dependent() && source.kind == MatrixProviderKind::Mapbox {
+            let departure_time = traffic.get_departure_times()?[0];
+            let provider = source.get_traffic_provider(&departure_time);
+            let matrices = build_matrices(provider.as_ref(), problem, profiles, None).await?;
+            return Ok((matrices, None));
+        }
+    }
+
+    if source.kind == MatrixProviderKind::Geometric {
+        let provider = source.get_provider();
+        let matrices = build_matrices(provider.as_ref(), problem, profiles, traffic).await?;
+        return Ok((matrices, None));
+    }
+
+    let provider = CachedProvider::from_env(source.kind.name(), source.get_provider());
+    let matrices = build_matrices(&provider, problem, profiles, traffic).await?;
+    Ok((matrices, Some(provider.get_stats())))
+}
+
+/// Builds a single matrix through the given provider, caching the pairs routed by a remote
+/// service the same way as the matrices of a problem.
+pub async fn get_cached_routing_matrix(
+    source: &MatrixSource,
+    sources: &[Location],
+    destinations: &[Location],
+    profile: TravelProfile,
+) -> Result<(RoutingMatrix, Option<CacheStats>), MatrixFail> {
+    if source.kind == MatrixProviderKind::Geometric {
+        let matrix = get_routing_matrix(
+            source.get_provider().as_ref(),
+            sources,
+            destinations,
+            profile,
+        )
+        .await?;
+        return Ok((matrix, None));
+    }
+
+    let provider = CachedProvider::from_env(source.kind.name(), source.get_provider());
+    let matrix = get_routing_matrix(&provider, sources, destinations, profile).await?;
+    Ok((matrix, Some(provider.get_stats())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_locations() -> Vec<Location> {
+        vec![
+            Location {
+                lat: 51.455691,
+                lng: -2.586119,
+            },
+            Location {
+                lat: 51.375932,
+                lng: -2.382291,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_to_vrp_matrix() {
+        let matrix: RoutingMatrix = serde_json::from_str(
+            r#"{
+  "durations": [[0.0, 120.4, 300.0], [118.6, 0.0, null], [290.0, null, 0.0]],
+  "distances": [[0.0, 900.0, 2500.0], [880.0, 0.0, null], [2400.0, null, 0.0]]
+}"#,
+        )
+        .unwrap();
+
+        let matrix = matrix.to_vrp_matrix(TravelProfile::Van);
+        assert_eq!(matrix.profile, Some("van".to_string()));
+        assert_eq!(matrix.travel_times, vec![0, 120, 300, 119, 0, 0, 290, 0, 0]);
+        assert_eq!(matrix.distances, vec![0, 900, 2500, 880, 0, 0, 2400, 0, 0]);
+        assert_eq!(matrix.error_codes, Some(vec![0, 0, 0, 0, 0, 1, 0, 1, 0]));
+    }
+
+    #[test]
+    fn test_reachable_to_vrp_matrix() {
+        let matrix = RoutingMatrix {
+            durations: vec![vec![Some(0.0), Some(60.0)], vec![Some(60.0), Some(0.0)]],
+            distances: vec![vec![Some(0.0), Some(500.0)], vec![Some(500.0), Some(0.0)]],
+        };
+
+        let matrix = matrix.to_vrp_matrix(TravelProfile::Car);
+        assert_eq!(matrix.travel_times, vec![0, 60, 60, 0]);
+        assert_eq!(matrix.error_codes, None);
+    }
+
+    #[test]
+    fn test_get_coordinates_query() {
+        let locations = get_locations();
+        let (coordinates, query) = get_coordinates_query(&locations, &locations);
+        assert_eq!(coordinates.len(), 2);
+        assert!(query.is_empty());
+
+        let (coordinates, query) = get_coordinates_query(&locations[..1], &locations);
+        assert_eq!(coordinates.len(), 3);
+        assert_eq!(query[0], ("sources".to_string(), "0".to_string()));
+        assert_eq!(query[1], ("destinations".to_string(), "1;2".to_string()));
+        assert_eq!(
+            format_coordinates(&coordinates[..1]),
+            "-2.586119,51.455691".to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_provider_kind() {
+        let kind: MatrixProviderKind = serde_json::from_str(r#""osrm""#).unwrap();
+        assert_eq!(kind, MatrixProviderKind::Osrm);
+        assert_eq!(kind.name(), "osrm");
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_geometric_matrix() {
+        let locations = get_locations();
+        let (matrix, matrix_cache) = get_cached_routing_matrix(
+            &MatrixSource::new(MatrixProviderKind::Geometric),
+            &locations[..1],
+            &locations,
+            TravelProfile::Car,
+        )
+        .await
+        .unwrap();
+
+        assert!(matrix.has_shape(1, 2));
+        assert_eq!(matrix_cache, None);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::Location;
+
+use crate::matrix::{MatrixFail, MatrixProvider, RoutingMatrix};
+use crate::profile::TravelProfile;
+use crate::redis_manager;
+
+const MATRIX_CACHE_KEY_PREFIX: &str = "MATRIX";
+const PAIR_SEPARATOR: &str = ";";
+pub const DEFAULT_MATRIX_CACHE_TTL_SECONDS: usize = 7 * 24 * 60 * 60;
+
+/// How many of the pairs in the matrices of a request were served from the cache.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub hit_rate: f64,
+}
+
+impl CacheStats {
+    fn add(&mut self, hits: usize, misses: usize) {
+        self.hits += hits;
+        self.misses += misses;
+        let total = self.hits + self.misses;
+        self.hit_rate = if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        };
+    }
+}
+
+/// Caches the duration and distance of every routed pair in redis, keyed by the coordinates
+/// rounded to about a metre, so that only the pairs that haven't been seen recently are routed
+/// again.
+pub struct CachedProvider {
+    name: String,
+    provider: Box<dyn MatrixProvider>,
+    ttl: usize,
+    stats: Mutex<CacheStats>,
+}
+
+impl CachedProvider {
+    pub fn new(name: &str, provider: Box<dyn MatrixProvider>, ttl: usize) -> CachedProvider {
+        CachedProvider {
+            name: name.to_string(),
+            provider,
+            ttl,
+            stats: Mutex::new(CacheStats::default()),
+        }
+    }
+
+    /// Keeps pairs for `MATRIX_CACHE_TTL` seconds, or a week by default.
+    pub fn from_env(name: &str, provider: Box<dyn MatrixProvider>) -> CachedProvider {
+        let ttl = parse_ttl(env::var("MATRIX_CACHE_TTL").ok());
+        CachedProvider::new(name, provider, ttl)
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        self.stats
+            .lock()
+            .expect("Matrix cache stats are poisoned")
+            .clone()
+    }
+
+    fn build_key(&self, routing_key: &str, from: &Location, to: &Location) -> String {
+        format!(
+            "{}:{}:{}:{:.5},{:.5}:{:.5},{:.5}",
+            MATRIX_CACHE_KEY_PREFIX, self.name, routing_key, from.lat, from.lng, to.lat, to.lng
+        )
+    }
+}
+
+/// Redis refuses to expire keys after no time at all, so a TTL has to be a positive number of
+/// seconds, falling back to the default otherwise.
+fn parse_ttl(ttl: Option<String>) -> usize {
+    match ttl.map(|ttl| ttl.parse::<usize>()) {
+        None => DEFAULT_MATRIX_CACHE_TTL_SECONDS,
+        Some(Ok(ttl)) if ttl > 0 => ttl,
+        Some(_) => {
+            log::warn!(
+                "MATRIX_CACHE_TTL must be a positive number of seconds, using {} instead",
+                DEFAULT_MATRIX_CACHE_TTL_SECONDS
+            );
+            DEFAULT_MATRIX_CACHE_TTL_SECONDS
+        }
+    }
+}
+
+fn build_value(duration: f64, distance: f64) -> String {
+    format!("{}{}{}", duration, PAIR_SEPARATOR, distance)
+}
+
+fn parse_value(value: &str) -> Option<(f64, f64)> {
+    let mut parts = value.split(PAIR_SEPARATOR);
+    let duration = parts.next()?.parse().ok()?;
+    let distance = parts.next()?.parse().ok()?;
+    Some((duration, distance))
+}
+
+#[async_trait]
+impl MatrixProvider for CachedProvider {
+    async fn get_matrix(
+        &self,
+        sources: &[Location],
+        destinations: &[Location],
+        profile: TravelProfile,
+    ) -> Result<RoutingMatrix, MatrixFail> {
+        let routing_key = self.provider.get_routing_key(profile);
+        let keys: Vec<String> = sources
+            .iter()
+            .flat_map(|from| {
+                destinations
+                    .iter()
+                    .map(|to| self.build_key(&routing_key, from, to))
+                    .collect::<Vec<String>>()
+            })
+            .collect();
+        // redis is blocking, and an unavailable cache is treated as a cache that has nothing in it
+        let lookup = keys.clone();
+        let cached = tokio::task::spawn_blocking(move || redis_manager::get_many(&lookup))
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| vec![None; keys.len()]);
+
+        let mut matrix = RoutingMatrix {
+            durations: vec![vec![None; destinations.len()]; sources.len()],
+            distances: vec![vec![None; destinations.len()]; sources.len()],
+        };
+        let mut missing = vec![];
+        for (index, value) in cached.iter().enumerate() {
+            let (source, destination) = (index / destinations.len(), index % destinations.len());
+            match value.as_deref().and_then(parse_value) {
+                Some((duration, distance)) => {
+                    matrix.durations[source][destination] = Some(duration);
+                    matrix.distances[source][destination] = Some(distance);
+                }
+                None => missing.push((source, destination)),
+            }
+        }
+
+        self.stats
+            .lock()
+            .expect("Matrix cache stats are poisoned")
+            .add(keys.len() - missing.len(), missing.len());
+        if missing.is_empty() {
+            return Ok(matrix);
+        }
+
+        // route the smallest block of sources and destinations that covers every missing pair
+        let missing_sources: Vec<usize> = missing
+            .iter()
+            .map(|(source, _)| *source)
+            .collect::<BTreeSet<usize>>()
+            .into_iter()
+            .collect();
+        let missing_destinations: Vec<usize> = missing
+            .iter()
+            .map(|(_, destination)| *destination)
+            .collect::<BTreeSet<usize>>()
+            .into_iter()
+            .collect();
+        let routed = self
+            .provider
+            .get_matrix(
+                &missing_sources
+                    .iter()
+                    .map(|index| sources[*index].clone())
+                    .collect::<Vec<Location>>(),
+                &missing_destinations
+                    .iter()
+                    .map(|index| destinations[*index].clone())
+                    .collect::<Vec<Location>>(),
+                profile,
+            )
+            .await?;
+
+        let mut values = vec![];
+        for (source, destination) in missing {
+            let row = missing_sources.binary_search(&source).unwrap();
+            let column = missing_destinations.binary_search(&destination).unwrap();
+            let duration = routed.durations.get(row).and_then(|row| row.get(column));
+            let distance = routed.distances.get(row).and_then(|row| row.get(column));
+            if let (Some(Some(duration)), Some(Some(distance))) = (duration, distance) {
+                matrix.durations[source][destination] = Some(*duration);
+                matrix.distances[source][destination] = Some(*distance);
+                values.push((
+                    keys[source * destinations.len() + destination].clone(),
+                    build_value(*duration, *distance),
+                ));
+            }
+        }
+
+        let (count, ttl) = (values.len(), self.ttl);
+        let stored =
+            tokio::task::spawn_blocking(move || redis_manager::set_many_with_expiry(&values, ttl))
+                .await
+                .ok()
+                .flatten();
+        if stored.is_none() {
+            log::warn!("Unable to cache {} matrix pairs", count);
+        }
+
+        Ok(matrix)
+    }
+
+    fn get_routing_key(&self, profile: TravelProfile) -> String {
+        self.provider.get_routing_key(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometric::GeometricProvider;
+
+    fn get_locations() -> Vec<Location> {
+        vec![
+            Location {
+                lat: 51.455691,
+                lng: -2.586119,
+            },
+            Location {
+                lat: 51.375932,
+                lng: -2.382291,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_build_key() {
+        let provider = CachedProvider::new("test", Box::new(GeometricProvider::default()), 60);
+        let locations = get_locations();
+        assert_eq!(
+            provider.build_key("car", &locations[0], &locations[1]),
+            "MATRIX:test:car:51.45569,-2.58612:51.37593,-2.38229"
+        );
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value(&build_value(60.5, 900.0)), Some((60.5, 900.0)));
+        assert_eq!(parse_value("60.5"), None);
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl(None), DEFAULT_MATRIX_CACHE_TTL_SECONDS);
+        assert_eq!(parse_ttl(Some("60".to_string())), 60);
+        assert_eq!(
+            parse_ttl(Some("0".to_string())),
+            DEFAULT_MATRIX_CACHE_TTL_SECONDS
+        );
+        assert_eq!(
+            parse_ttl(Some("a day".to_string())),
+            DEFAULT_MATRIX_CACHE_TTL_SECONDS
+        );
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let mut stats = CacheStats::default();
+        stats.add(3, 1);
+        stats.add(0, 4);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 5);
+        assert_eq!(stats.hit_rate, 0.375);
+    }
+
+    #[tokio::test]
+    async fn test_cached_matrix() {
+        let name = format!("test{}", chrono::Utc::now().timestamp_nanos());
+        let provider = CachedProvider::new(&name, Box::new(GeometricProvider::default()), 60);
+        let locations = get_locations();
+
+        let routed = provider
+            .get_matrix(&locations, &locations, TravelProfile::Car)
+            .await
+            .unwrap();
+        assert_eq!(provider.get_stats().misses, 4);
+
+        let cached = provider
+            .get_matrix(&locations, &locations, TravelProfile::Car)
+            .await
+            .unwrap();
+        assert_eq!(provider.get_stats().hits, 4);
+        assert_eq!(cached, routed);
+    }
+}
@@ -0,0 +1,171 @@
+use std::path::Path;
+
+use vrp_pragmatic::format::Location;
+
+use crate::postcode::Postcode;
+use crate::spatial::{NearbyPostcode, PostcodeIndex, PostcodePoint};
+
+/// Geocodes in both directions without redis, from the postcode dataset loaded into memory once.
+/// Postcodes are looked up by binary search over positions of the spatial index sorted by
+/// postcode, so the only thing stored twice is a four byte position per postcode. Postcodes are
+/// stored the way `Postcode` formats them, so that they compare equal to any parsed query.
+pub struct MemoryGeocoder {
+    index: PostcodeIndex,
+    by_postcode: Vec<u32>,
+}
+
+impl MemoryGeocoder {
+    pub fn new(points: Vec<PostcodePoint>) -> MemoryGeocoder {
+        let points = points
+            .into_iter()
+            .map(|point| PostcodePoint {
+                postcode: Postcode::parse(&point.postcode)
+                    .map(|postcode| postcode.to_string())
+                    .unwrap_or(point.postcode),
+                ..point
+            })
+            .collect();
+        let index = PostcodeIndex::new(points);
+        let mut by_postcode: Vec<u32> = (0..index.len() as u32).collect();
+        by_postcode.sort_unstable_by(|a, b| {
+            let postcode = |position: &u32| &index.get(*position as usize).unwrap().postcode;
+            postcode(a).cmp(postcode(b))
+        });
+
+        MemoryGeocoder { index, by_postcode }
+    }
+
+    /// Reads every postcode from a CSV of postcode, latitude and longitude, skipping any rows
+    /// without usable coordinates.
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> Option<MemoryGeocoder> {
+        let points: Vec<PostcodePoint> = csv::Reader::from_path(path)
+            .ok()?
+            .records()
+            .filter_map(|record| {
+                let record = record.ok()?;
+                Some(PostcodePoint {
+                    postcode: record.get(0)?.to_string(),
+                    location: Location {
+                        lat: record.get(1)?.parse().ok()?,
+                        lng: record.get(2)?.parse().ok()?,
+                    },
+                })
+            })
+            .collect();
+        Some(MemoryGeocoder::new(points))
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn get_coordinates(&self, postcode: &Postcode) -> Option<Location> {
+        let postcode = postcode.to_string();
+        let position = self
+            .by_postcode
+            .binary_search_by(|position| {
+                self.index
+                    .get(*position as usize)
+                    .unwrap()
+                    .postcode
+                    .cmp(&postcode)
+            })
+            .ok()?;
+        self.index
+            .get(self.by_postcode[position] as usize)
+            .map(|point| point.location.clone())
+    }
+
+    pub fn nearest(&self, location: &Location) -> Option<NearbyPostcode> {
+        self.index.nearest(location)
+    }
+
+    pub fn nearby(
+        &self,
+        location: &Location,
+        k: Option<usize>,
+        radius: Option<f64>,
+    ) -> Vec<NearbyPostcode> {
+        self.index.nearby(location, k, radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_geocoder() -> MemoryGeocoder {
+        let points = vec![
+            ("BS1 3AA", 51.455691, -2.586119),
+            ("BA2 1AA", 51.375932, -2.382291),
+            ("AB1 0AJ", 57.099011, -2.252854),
+            ("BS1 1AA", 51.449516, -2.57837),
+        ];
+        MemoryGeocoder::new(
+            points
+                .into_iter()
+                .map(|(postcode, lat, lng)| PostcodePoint {
+                    postcode: postcode.to_string(),
+                    location: Location { lat, lng },
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_get_coordinates() {
+        let geocoder = get_geocoder();
+        assert_eq!(geocoder.len(), 4);
+        let get_coordinates =
+            |postcode: &str| geocoder.get_coordinates(&Postcode::parse(postcode).unwrap());
+        for postcode in &["BS1 3AA", "bs13aa", "BS1-3AA"] {
+            assert_eq!(get_coordinates(postcode).unwrap().lat, 51.455691);
+        }
+        assert_eq!(get_coordinates("AB1 0AJ").unwrap().lng, -2.252854);
+        assert_eq!(get_coordinates("BS1 2AA"), None);
+    }
+
+    #[test]
+    fn test_stored_postcodes_are_formatted() {
+        let geocoder = MemoryGeocoder::new(vec![PostcodePoint {
+            postcode: "bs13aa".to_string(),
+            location: Location {
+                lat: 51.455691,
+                lng: -2.586119,
+            },
+        }]);
+        let postcode = Postcode::parse("BS1 3AA").unwrap();
+        assert!(geocoder.get_coordinates(&postcode).is_some());
+        assert_eq!(
+            geocoder
+                .nearest(&Location {
+                    lat: 51.45,
+                    lng: -2.58
+                })
+                .unwrap()
+                .postcode,
+            "BS1 3AA"
+        );
+    }
+
+    #[test]
+    fn test_nearest() {
+        let geocoder = get_geocoder();
+        let nearest = geocoder
+            .nearest(&Location {
+                lat: 51.45,
+                lng: -2.58,
+            })
+            .unwrap();
+        assert_eq!(nearest.postcode, "BS1 1AA");
+    }
+
+    #[test]
+    fn test_from_missing_csv() {
+        assert!(MemoryGeocoder::from_csv("missing.csv").is_none());
+    }
+}
@@ -1,7 +1,221 @@
-// use osrm::Osrm;
-//
-// pub fn table(list: Vec<String>) {
-//
-//     let osrm = Osrm::new("/Volumes/dev/osrm-data/great-britain-latest.osrm");
-//
-// }
+use std::env;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::Location;
+
+use crate::directions::{DirectionsFail, DirectionsResponse, Route, RouteProvider};
+use crate::matrix;
+use crate::matrix::{MatrixFail, MatrixProvider, RoutingMatrix};
+use crate::profile::TravelProfile;
+
+const DEFAULT_OSRM_URL: &str = "http://localhost:5000";
+
+/// The response of the OSRM `table` service.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    pub code: String,
+    #[serde(default)]
+    pub durations: Option<Vec<Vec<Option<f64>>>>,
+    #[serde(default)]
+    pub distances: Option<Vec<Vec<Option<f64>>>>,
+}
+
+impl Table {
+    /// The matrix of a successful table, which only has both annotations when they were asked for.
+    pub fn into_matrix(self) -> Option<RoutingMatrix> {
+        match self {
+            Table {
+                code,
+                durations: Some(durations),
+                distances: Some(distances),
+            } if code == "Ok" => Some(RoutingMatrix {
+                durations,
+                distances,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Routes through the `table` service of an OSRM HTTP server.
+pub struct OsrmProvider {
+    url: String,
+}
+
+impl OsrmProvider {
+    pub fn new(url: &str) -> OsrmProvider {
+        OsrmProvider {
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Uses the server at `OSRM_URL`, or a local one on the default port.
+    pub fn from_env() -> OsrmProvider {
+        OsrmProvider::new(&env::var("OSRM_URL").unwrap_or_else(|_| DEFAULT_OSRM_URL.to_string()))
+    }
+
+    pub async fn get_table(
+        &self,
+        sources: &[Location],
+        destinations: &[Location],
+        routing_profile: &str,
+    ) -> Option<Table> {
+        let (locations, mut query) = matrix::get_coordinates_query(sources, destinations);
+        query.push(("annotations".to_string(), "duration,distance".to_string()));
+
+        let url = format!(
+            "{}/table/v1/{}/{}",
+            self.url,
+            routing_profile,
+            matrix::format_coordinates(&locations)
+        );
+        let response_body = reqwest::Client::new()
+            .get(&url)
+            .query(&query)
+            .send()
+            .await
+            .ok()?
+            .text()
+            .await
+            .ok()?;
+
+        serde_json::from_str(response_body.as_str()).ok()
+    }
+
+    pub async fn get_directions(
+        &self,
+        waypoints: &[Location],
+        routing_profile: &str,
+    ) -> Option<DirectionsResponse> {
+        let url = format!(
+            "{}/route/v1/{}/{}",
+            self.url,
+            routing_profile,
+            matrix::format_coordinates(waypoints)
+        );
+        let response_body = reqwest::Client::new()
+            .get(&url)
+            .query(&[("geometries", "geojson"), ("overview", "full")])
+            .send()
+            .await
+            .ok()?
+            .text()
+            .await
+            .ok()?;
+
+        serde_json::from_str(response_body.as_str()).ok()
+    }
+}
+
+#[async_trait]
+impl MatrixProvider for OsrmProvider {
+    async fn get_matrix(
+        &self,
+        sources: &[Location],
+        destinations: &[Location],
+        profile: TravelProfile,
+    ) -> Result<RoutingMatrix, MatrixFail> {
+        let routing_profile = profile.routing_profile();
+        self.get_table(sources, destinations, routing_profile)
+            .await
+            .and_then(Table::into_matrix)
+            .ok_or_else(|| {
+                MatrixFail::new(&format!(
+                    "Unable to fetch the {} matrix from OSRM at {}",
+                    routing_profile, self.url
+                ))
+            })
+    }
+
+    fn get_routing_key(&self, profile: TravelProfile) -> String {
+        profile.routing_profile().to_string()
+    }
+}
+
+#[async_trait]
+impl RouteProvider for OsrmProvider {
+    async fn get_route(
+        &self,
+        waypoints: &[Location],
+        profile: TravelProfile,
+    ) -> Result<Route, DirectionsFail> {
+        let routing_profile = profile.routing_profile();
+        self.get_directions(waypoints, routing_profile)
+            .await
+            .and_then(|directions| directions.into_route(waypoints.len()))
+            .ok_or_else(|| {
+                DirectionsFail::new(&format!(
+                    "Unable to fetch {} directions from OSRM at {}",
+                    routing_profile, self.url
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    #[test]
+    fn test_table_into_matrix() {
+        let table: Table = serde_json::from_value(serde_json::json!({
+            "code": "Ok",
+            "durations": [[0.0, 600.0], [610.0, 0.0]],
+            "distances": [[0.0, 800.0], [820.0, null]]
+        }))
+        .unwrap();
+        let matrix = table.into_matrix().unwrap();
+        assert_eq!(matrix.durations[1][0], Some(610.0));
+        assert_eq!(matrix.distances[1][1], None);
+
+        let table: Table = serde_json::from_value(serde_json::json!({
+            "code": "InvalidQuery",
+            "durations": [[0.0]]
+        }))
+        .unwrap();
+        assert!(table.into_matrix().is_none());
+    }
+
+    // binds a local port for the mock server, which isn't possible everywhere tests are run
+    #[tokio::test]
+    #[ignore]
+    async fn test_get_matrix_from_mock_server() {
+        let table = warp::path!("table" / "v1" / "walking" / String).map(|_| {
+            warp::reply::json(&serde_json::json!({
+                "code": "Ok",
+                "durations": [[0.0, 600.0], [610.0, 0.0]],
+                "distances": [[0.0, 800.0], [820.0, null]]
+            }))
+        });
+        let (addr, server) = warp::serve(table).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(server);
+
+        let locations = vec![
+            Location {
+                lat: 51.455691,
+                lng: -2.586119,
+            },
+            Location {
+                lat: 51.449516,
+                lng: -2.57837,
+            },
+        ];
+        let provider = OsrmProvider::new(&format!("http://{}/", addr));
+        let matrix = provider
+            .get_matrix(&locations, &locations, TravelProfile::Foot)
+            .await
+            .unwrap();
+
+        assert_eq!(matrix.durations[1][0], Some(610.0));
+        assert_eq!(matrix.distances[1][1], None);
+    }
+
+    #[tokio::test]
+    async fn test_get_matrix_without_server() {
+        let provider = OsrmProvider::new("http://127.0.0.1:1");
+        let result = provider.get_matrix(&[], &[], TravelProfile::Car).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+use std::str::FromStr;
+
+const BFPO: &str = "BFPO";
+const GIROBANK: &str = "GIR0AA";
+
+/// Letters that never appear in the first position of a postcode.
+const INVALID_FIRST_LETTERS: &str = "QVX";
+/// Letters that never appear in the second position of a postcode.
+const INVALID_SECOND_LETTERS: &str = "IJZ";
+/// The only letters that can end an `A9A` district, such as `W1A`.
+const VALID_THIRD_LETTERS: &str = "ABCDEFGHJKPSTUW";
+/// The only letters that can end an `AA9A` district, such as `EC1A`.
+const VALID_FOURTH_LETTERS: &str = "ABEHMNPRVWXY";
+/// Letters that never appear in the unit of a postcode.
+const INVALID_UNIT_LETTERS: &str = "CIKMOV";
+
+/// Why some text isn't a UK postcode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostcodeError {
+    Empty,
+    InvalidCharacter(char),
+    InvalidLength(usize),
+    InvalidOutward(String),
+    InvalidInward(String),
+    InvalidBfpo(String),
+}
+
+impl fmt::Display for PostcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostcodeError::Empty => write!(f, "The postcode is empty"),
+            PostcodeError::InvalidCharacter(char) => {
+                write!(f, "The postcode can't contain the character {:?}", char)
+            }
+            PostcodeError::InvalidLength(length) => write!(
+                f,
+                "The postcode has {} letters and digits instead of between 5 and 7",
+                length
+            ),
+            PostcodeError::InvalidOutward(outward) => {
+                write!(f, "{} isn't a valid postcode area and district", outward)
+            }
+            PostcodeError::InvalidInward(inward) => write!(
+                f,
+                "{} isn't a valid postcode sector and unit, which are a digit and two letters",
+                inward
+            ),
+            PostcodeError::InvalidBfpo(number) => {
+                write!(f, "BFPO {} isn't a number of up to four digits", number)
+            }
+        }
+    }
+}
+
+/// A UK postcode split into its outward code, the area and district such as `BS1`, and its inward
+/// code, the sector and unit such as `3AA`. British Forces Post Office numbers keep `BFPO` as the
+/// outward code and the number as the inward code.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Postcode {
+    outward: String,
+    inward: String,
+}
+
+impl Postcode {
+    /// Reads a postcode in any case, with or without spaces and hyphens between its parts.
+    pub fn parse(text: &str) -> Result<Postcode, PostcodeError> {
+        let mut normalised = String::with_capacity(text.len());
+        for char in text.chars() {
+            match char {
+                char if char.is_whitespace() || char == '-' => {}
+                char if char.is_ascii_alphanumeric() => normalised.push(char.to_ascii_uppercase()),
+                char => return Err(PostcodeError::InvalidCharacter(char)),
+            }
+        }
+
+        if normalised.is_empty() {
+            return Err(PostcodeError::Empty);
+        }
+        if let Some(number) = normalised.strip_prefix(BFPO) {
+            return parse_bfpo(number);
+        }
+        if normalised == GIROBANK {
+            return Ok(Postcode {
+                outward: "GIR".to_string(),
+                inward: "0AA".to_string(),
+            });
+        }
+
+        if normalised.len() < 5 || normalised.len() > 7 {
+            return Err(PostcodeError::InvalidLength(normalised.len()));
+        }
+        let (outward, inward) = normalised.split_at(normalised.len() - 3);
+        if !is_valid_outward(outward) {
+            return Err(PostcodeError::InvalidOutward(outward.to_string()));
+        }
+        if !is_valid_inward(inward) {
+            return Err(PostcodeError::InvalidInward(inward.to_string()));
+        }
+
+        Ok(Postcode {
+            outward: outward.to_string(),
+            inward: inward.to_string(),
+        })
+    }
+
+    pub fn is_bfpo(&self) -> bool {
+        self.outward == BFPO
+    }
+
+    pub fn outward(&self) -> &str {
+        &self.outward
+    }
+
+    pub fn inward(&self) -> &str {
+        &self.inward
+    }
+
+    /// The letters the postcode starts with, such as `BS` for `BS1 3AA`.
+    pub fn area(&self) -> &str {
+        let end = self
+            .outward
+            .find(|char: char| char.is_ascii_digit())
+            .unwrap_or(self.outward.len());
+        &self.outward[..end]
+    }
+
+    /// The outward code, such as `BS1` for `BS1 3AA`.
+    pub fn district(&self) -> &str {
+        &self.outward
+    }
+
+    /// The district followed by the first digit of the inward code, such as `BS1 3` for `BS1 3AA`.
+    pub fn sector(&self) -> Option<String> {
+        if self.is_bfpo() {
+            None
+        } else {
+            Some(format!("{} {}", self.outward, &self.inward[..1]))
+        }
+    }
+
+    /// The letters the postcode ends with, such as `AA` for `BS1 3AA`.
+    pub fn unit(&self) -> Option<&str> {
+        if self.is_bfpo() {
+            None
+        } else {
+            Some(&self.inward[1..])
+        }
+    }
+
+    /// The postcode without the space, as postcodes are keyed in redis.
+    pub fn to_key(&self) -> String {
+        format!("{}{}", self.outward, self.inward)
+    }
+}
+
+impl fmt::Display for Postcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.outward, self.inward)
+    }
+}
+
+impl FromStr for Postcode {
+    type Err = PostcodeError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Postcode::parse(text)
+    }
+}
+
+fn parse_bfpo(number: &str) -> Result<Postcode, PostcodeError> {
+    if number.is_empty() || number.len() > 4 || !number.chars().all(|char| char.is_ascii_digit()) {
+        return Err(PostcodeError::InvalidBfpo(number.to_string()));
+    }
+    Ok(Postcode {
+        outward: BFPO.to_string(),
+        inward: number.to_string(),
+    })
+}
+
+/// Outward codes are one of `A9`, `A99`, `A9A`, `AA9`, `AA99` or `AA9A`.
+fn is_valid_outward(outward: &str) -> bool {
+    let chars: Vec<char> = outward.chars().collect();
+    let is_letter = |char: char, letters: &str, valid: bool| {
+        char.is_ascii_alphabetic() && letters.contains(char) == valid
+    };
+
+    match chars.as_slice() {
+        [first, rest @ ..] if is_letter(*first, INVALID_FIRST_LETTERS, false) => match rest {
+            [digit] => digit.is_ascii_digit(),
+            [digit, last] if digit.is_ascii_digit() => {
+                last.is_ascii_digit() || is_letter(*last, VALID_THIRD_LETTERS, true)
+            }
+            [second, digit] => {
+                is_letter(*second, INVALID_SECOND_LETTERS, false) && digit.is_ascii_digit()
+            }
+            [second, digit, last] => {
+                is_letter(*second, INVALID_SECOND_LETTERS, false)
+                    && digit.is_ascii_digit()
+                    && (last.is_ascii_digit() || is_letter(*last, VALID_FOURTH_LETTERS, true))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Inward codes are a sector digit followed by two unit letters.
+fn is_valid_inward(inward: &str) -> bool {
+    let chars: Vec<char> = inward.chars().collect();
+    match chars.as_slice() {
+        [sector, unit @ ..] => {
+            sector.is_ascii_digit()
+                && unit
+                    .iter()
+                    .all(|char| char.is_ascii_alphabetic() && !INVALID_UNIT_LETTERS.contains(*char))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        for (text, expected) in &[
+            ("M1 1AE", "M1 1AE"),
+            ("b338th", "B33 8TH"),
+            ("W1A 0AX", "W1A 0AX"),
+            ("CR2-6XH", "CR2 6XH"),
+            (" dn55 1pt ", "DN55 1PT"),
+            ("EC1A 1BB", "EC1A 1BB"),
+            ("gir 0aa", "GIR 0AA"),
+            ("BFPO 801", "BFPO 801"),
+        ] {
+            assert_eq!(Postcode::parse(text).unwrap().to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_parts() {
+        let postcode: Postcode = "ec1a1bb".parse().unwrap();
+        assert_eq!(postcode.area(), "EC");
+        assert_eq!(postcode.district(), "EC1A");
+        assert_eq!(postcode.sector(), Some("EC1A 1".to_string()));
+        assert_eq!(postcode.unit(), Some("BB"));
+        assert_eq!(postcode.to_key(), "EC1A1BB");
+
+        let postcode = Postcode::parse("BFPO1234").unwrap();
+        assert!(postcode.is_bfpo());
+        assert_eq!(postcode.area(), "BFPO");
+        assert_eq!(postcode.sector(), None);
+        assert_eq!(postcode.unit(), None);
+    }
+
+    #[test]
+    fn test_invalid_postcodes() {
+        assert_eq!(Postcode::parse(" "), Err(PostcodeError::Empty));
+        assert_eq!(
+            Postcode::parse("BS1;3AA"),
+            Err(PostcodeError::InvalidCharacter(';'))
+        );
+        assert_eq!(
+            Postcode::parse("BS13AAAA"),
+            Err(PostcodeError::InvalidLength(8))
+        );
+        assert_eq!(
+            Postcode::parse("BS6 666"),
+            Err(PostcodeError::InvalidInward("666".to_string()))
+        );
+        assert_eq!(
+            Postcode::parse("BS1 3CA"),
+            Err(PostcodeError::InvalidInward("3CA".to_string()))
+        );
+        for outward in &["QS1", "BI1", "W1Q", "EC1Z", "11A", "BSS"] {
+            assert_eq!(
+                Postcode::parse(&format!("{} 3AA", outward)),
+                Err(PostcodeError::InvalidOutward(outward.to_string()))
+            );
+        }
+        assert_eq!(
+            Postcode::parse("BFPO 12345"),
+            Err(PostcodeError::InvalidBfpo("12345".to_string()))
+        );
+    }
+}
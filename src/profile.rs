@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::problem::Profile;
+
+/// The travel modes a simple trip vehicle can be given, each routed with its own profile.
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TravelProfile {
+    #[default]
+    Car,
+    Van,
+    Truck,
+    Bicycle,
+    Foot,
+}
+
+impl TravelProfile {
+    pub fn from_name(name: &str) -> Option<TravelProfile> {
+        serde_json::from_value(serde_json::Value::String(name.to_lowercase())).ok()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TravelProfile::Car => "car",
+            TravelProfile::Van => "van",
+            TravelProfile::Truck => "truck",
+            TravelProfile::Bicycle => "bicycle",
+            TravelProfile::Foot => "foot",
+        }
+    }
+
+    /// Average speed in metres per second, used whenever no routing matrix is given.
+    pub fn speed(self) -> f64 {
+        match self {
+            TravelProfile::Car => 17.0, // average 40mph
+            TravelProfile::Van => 15.0,
+            TravelProfile::Truck => 13.0,
+            TravelProfile::Bicycle => 4.5,
+            TravelProfile::Foot => 1.4,
+        }
+    }
+
+    /// The routing profile Mapbox and OSRM route this travel profile with. Vans and trucks share
+    /// the driving profile, as neither service has vehicle size restrictions on its matrices.
+    pub fn routing_profile(self) -> &'static str {
+        match self {
+            TravelProfile::Car | TravelProfile::Van | TravelProfile::Truck => "driving",
+            TravelProfile::Bicycle => "cycling",
+            TravelProfile::Foot => "walking",
+        }
+    }
+
+    /// Whether the profile drives on the roads, and so is slowed down by traffic.
+    pub fn is_motorised(self) -> bool {
+        self.routing_profile() == "driving"
+    }
+
+    pub fn to_pragmatic_profile(self) -> Profile {
+        Profile {
+            name: self.name().to_string(),
+            profile_type: self.name().to_string(),
+            speed: Some(self.speed()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialise_profile() {
+        let profiles: Vec<TravelProfile> = serde_json::from_str(r#"["van", "foot"]"#).unwrap();
+        assert_eq!(profiles, vec![TravelProfile::Van, TravelProfile::Foot]);
+        assert!(serde_json::from_str::<TravelProfile>(r#""boat""#).is_err());
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            TravelProfile::from_name("Truck"),
+            Some(TravelProfile::Truck)
+        );
+        assert_eq!(TravelProfile::from_name("normal_car"), None);
+    }
+
+    #[test]
+    fn test_routing_profile() {
+        assert_eq!(TravelProfile::Van.routing_profile(), "driving");
+        assert_eq!(TravelProfile::Bicycle.routing_profile(), "cycling");
+        assert_eq!(TravelProfile::Foot.routing_profile(), "walking");
+    }
+
+    #[test]
+    fn test_to_pragmatic_profile() {
+        let profile = TravelProfile::Bicycle.to_pragmatic_profile();
+        assert_eq!(profile.name, "bicycle");
+        assert_eq!(profile.speed, Some(4.5));
+        assert_eq!(
+            TravelProfile::default().to_pragmatic_profile().speed,
+            Some(17.0)
+        );
+    }
+}
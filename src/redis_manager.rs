@@ -6,7 +6,7 @@ use serde::de::DeserializeOwned;
 use serde::export::fmt::Display;
 use serde::Serialize;
 
-use crate::geocoding::{COORDINATES_SEPARATOR, POSTCODE_TABLE_NAME};
+use crate::geocoding::POSTCODE_TABLE_NAME;
 
 fn connect_and_query<F, T>(mut action: F) -> Option<T>
 where
@@ -22,23 +22,28 @@ fn get_redis_client() -> RedisResult<Client> {
     redis::Client::open("redis://127.0.0.1/")
 }
 
+/// Whether a redis server can be reached at all.
+pub fn is_available() -> bool {
+    connect_and_query(|mut connection| redis::cmd("PING").query::<String>(&mut connection).ok())
+        .is_some()
+}
+
 pub fn get_coordinates(postcode: &str) -> Option<String> {
     connect_and_query(|mut connection| connection.hget(POSTCODE_TABLE_NAME, postcode).ok()?)
 }
 
-pub fn get_postcode(coordinates: Vec<f64>) -> Option<String> {
-    let coord_string = coordinates
-        .iter()
-        .map(|coord| coord.to_string())
-        .collect::<Vec<String>>()
-        .join(COORDINATES_SEPARATOR);
-
-    // TODO [#31]: fix this
+/// Gets the coordinates of many postcodes with a single `HMGET`, with `None` for any that are
+/// missing.
+pub fn get_many_coordinates(postcodes: &[String]) -> Option<Vec<Option<String>>> {
+    if postcodes.is_empty() {
+        return Some(vec![]);
+    }
     connect_and_query(|mut connection| {
-        redis::cmd("HSCAN")
-            .arg(&["0", "MATCH", &coord_string])
+        redis::cmd("HMGET")
+            .arg(POSTCODE_TABLE_NAME)
+            .arg(postcodes)
             .query(&mut connection)
-            .ok()?
+            .ok()
     })
 }
 
@@ -57,14 +62,9 @@ pub fn del(table: &str, key: &str) -> Option<String> {
 }
 
 pub fn set<T: Serialize + Display>(table: &str, key: &str, value: T) -> Option<String> {
-    let client: Client = get_redis_client().expect("Unable to get a redis client");
-    let mut con = client.get_connection().expect("Unable to get a connection");
-
-    let result: RedisResult<i32> = con.hset(
-        table,
-        key,
-        serde_json::to_string(&value).expect("Unable to serialize value"),
-    );
+    let json = serde_json::to_string(&value).expect("Unable to serialize value");
+    let result: RedisResult<i32> =
+        connect_and_query(|mut connection| Some(connection.hset(table, key, &json)))?;
 
     match result {
         Err(err) => {
@@ -82,6 +82,58 @@ pub fn set<T: Serialize + Display>(table: &str, key: &str, value: T) -> Option<S
     }
 }
 
+/// Gets a plain string key holding JSON, as written by `set_with_expiry`.
+pub fn get_value<T: DeserializeOwned>(key: &str) -> Option<T> {
+    let result: String = connect_and_query(|mut connection| connection.get(key).ok()?)?;
+    serde_json::from_str(&result).ok()
+}
+
+/// Sets a plain string key to the JSON of `value`, expiring after `seconds`.
+pub fn set_with_expiry<T: Serialize + Display>(
+    key: &str,
+    value: T,
+    seconds: usize,
+) -> Option<String> {
+    let json = serde_json::to_string(&value).expect("Unable to serialize value");
+    let result: RedisResult<()> =
+        connect_and_query(|mut connection| Some(connection.set_ex(key, &json, seconds)))?;
+
+    match result {
+        Err(err) => {
+            log::error!("Couldn't write to redis, reason: {:?}", err.detail());
+            None
+        }
+        Ok(_) => {
+            let msg = format!("Wrote {} to key {} for {} seconds", value, key, seconds);
+            log::debug!("{}", msg);
+            Some(msg)
+        }
+    }
+}
+
+/// Gets many plain string keys in a single round trip, with `None` for any that are missing.
+pub fn get_many(keys: &[String]) -> Option<Vec<Option<String>>> {
+    if keys.is_empty() {
+        return Some(vec![]);
+    }
+    connect_and_query(|mut connection| redis::cmd("MGET").arg(keys).query(&mut connection).ok())
+}
+
+/// Sets many plain string keys in a single pipeline, each expiring after `seconds`.
+pub fn set_many_with_expiry(values: &[(String, String)], seconds: usize) -> Option<()> {
+    connect_and_query(|mut connection| {
+        let mut pipeline = redis::pipe();
+        values.iter().for_each(|(key, value)| {
+            pipeline.set_ex(key, value, seconds).ignore();
+        });
+        pipeline.query(&mut connection).ok()
+    })
+}
+
+pub fn increment(key: &str) -> Option<i64> {
+    connect_and_query(|mut connection| connection.incr(key, 1).ok())
+}
+
 pub fn count(table: &str) -> i32 {
     let client: Client = get_redis_client().unwrap();
     let mut con = client.get_connection().unwrap();
@@ -206,6 +258,41 @@ mod tests {
         assert_eq!(table_count, 0);
     }
 
+    #[test]
+    fn test_increment() {
+        let first = increment("TEST_INCREMENT").unwrap();
+        let second = increment("TEST_INCREMENT").unwrap();
+        assert_eq!(second, first + 1);
+    }
+
+    #[test]
+    fn test_set_and_get_many() {
+        let values = vec![
+            ("TEST_MANY_1".to_string(), "1".to_string()),
+            ("TEST_MANY_2".to_string(), "2".to_string()),
+        ];
+        set_many_with_expiry(&values, 60).unwrap();
+
+        let keys = vec![
+            "TEST_MANY_1".to_string(),
+            "TEST_MANY_MISSING".to_string(),
+            "TEST_MANY_2".to_string(),
+        ];
+        assert_eq!(
+            get_many(&keys).unwrap(),
+            vec![Some("1".to_string()), None, Some("2".to_string())]
+        );
+        assert_eq!(get_many(&[]), Some(vec![]));
+    }
+
+    #[test]
+    fn test_set_and_get_value() {
+        set_with_expiry("TEST_VALUE", "TEST", 60).unwrap();
+        let value: String = get_value("TEST_VALUE").unwrap();
+        assert_eq!(value, "TEST");
+        assert_eq!(get_value::<String>("TEST_VALUE_MISSING"), None);
+    }
+
     #[test]
     fn test_get() {
         set("TEST_GET_TABLE", "TEST", "TEST").unwrap();
@@ -213,9 +300,6 @@ mod tests {
         assert_eq!(get, "TEST")
     }
 
-    #[test]
-    fn test_get_postcode() {}
-
     #[test]
     fn test_get_coordinates() {
         let key = "IMAGINARYPOSTCODE";
@@ -225,6 +309,24 @@ mod tests {
         assert_eq!(coordinates, "\"0.0;0.0\"")
     }
 
+    #[test]
+    fn test_get_many_coordinates() {
+        let key = "IMAGINARYMANYPOSTCODE";
+        redis::Client::open("redis://127.0.0.1/")
+            .unwrap()
+            .get_connection()
+            .unwrap()
+            .hset::<&str, &str, &str, ()>(POSTCODE_TABLE_NAME, key, "1.0;2.0")
+            .unwrap();
+
+        let postcodes = vec![key.to_string(), "IMAGINARYMISSINGPOSTCODE".to_string()];
+        assert_eq!(
+            get_many_coordinates(&postcodes).unwrap(),
+            vec![Some("1.0;2.0".to_string()), None]
+        );
+        assert_eq!(get_many_coordinates(&[]), Some(vec![]));
+    }
+
     #[test]
     fn test_get_redis_client() {
         assert!(get_redis_client().is_ok())
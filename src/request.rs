@@ -1,21 +1,38 @@
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::problem;
 use vrp_pragmatic::format::problem::Fleet as ProblemFleet;
 use vrp_pragmatic::format::problem::Job as ProblemJob;
 use vrp_pragmatic::format::problem::Plan as ProblemPlan;
 use vrp_pragmatic::format::problem::{
-    JobPlace, JobTask, Profile, VehicleCosts, VehiclePlace, VehicleShift, VehicleType,
+    JobPlace, JobTask, VehicleCosts, VehiclePlace, VehicleShift, VehicleType,
 };
-use vrp_pragmatic::format::{problem, Location};
+use vrp_pragmatic::format::Location;
+use warp::{reject, Rejection};
 
 use crate::geocoding;
-use chrono::Duration;
+use crate::geocoding::{GeocodingFail, LocationEntry, UnresolvedLocation};
+use crate::geometric::{DistanceFormula, GeometricProvider};
+use crate::matrix::{MatrixFail, MatrixProviderKind, MatrixSource};
+use crate::profile::TravelProfile;
+use crate::response::ResponseFormat;
+use crate::solver::SolverConfig;
+use crate::traffic::TrafficOptions;
+use crate::ProblemFail;
+use chrono::{DateTime, Duration};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
+
+/// Coordinates of the postcodes of a simple trip that could be geocoded.
+type Locations = HashMap<String, Location>;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DetailedRequest {
     pub plan: Plan,
     pub fleet: Fleet,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solver_config: Option<SolverConfig>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -37,6 +54,8 @@ pub struct Delivery {
     pub places: Vec<Place>,
     pub priority: i64,
     pub properties: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub demand: Option<i32>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -59,6 +78,8 @@ pub struct Vehicle {
     pub costs: Costs,
     pub shifts: Vec<Shift>,
     pub capacity: i32,
+    #[serde(default)]
+    pub profile: TravelProfile,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -91,17 +112,131 @@ pub struct End {
 }
 
 impl DetailedRequest {
-    pub fn convert_to_internal_problem(self) -> vrp_pragmatic::format::problem::Problem {
-        let fleet = self
+    /// Geocodes the request into a pragmatic problem, failing with every location that couldn't be
+    /// resolved, or with the first job the solver couldn't take.
+    pub fn convert_to_internal_problem(&self) -> Result<problem::Problem, Rejection> {
+        let (locations, unresolved) = self.resolve_locations();
+        if !unresolved.is_empty() {
+            return Err(reject::custom(GeocodingFail::unresolved(unresolved)));
+        }
+
+        Ok(problem::Problem {
+            plan: ProblemPlan {
+                jobs: self
+                    .plan
+                    .jobs
+                    .iter()
+                    .map(|job| job.build_job(&locations))
+                    .collect::<Result<_, _>>()
+                    .map_err(reject::custom)?,
+                relations: None,
+            },
+            fleet: ProblemFleet {
+                vehicles: self.build_vehicles(&locations),
+                profiles: self
+                    .get_profiles()
+                    .into_iter()
+                    .map(TravelProfile::to_pragmatic_profile)
+                    .collect(),
+            },
+            objectives: None,
+            config: None,
+        })
+    }
+
+    /// Every postcode of the shifts and then of the job places, in the order they're given, along
+    /// with the vehicle or job it belongs to.
+    pub fn locations(&self) -> Vec<(LocationEntry, usize, &str)> {
+        let vehicles = self
             .fleet
             .vehicles
             .iter()
-            .map(|vehicle| {
-                vrp_pragmatic::format::problem::VehicleType {
-                    type_id: "vehicle".to_owned(), // TODO: understand type id's in Vehicle Type
+            .enumerate()
+            .flat_map(|(index, vehicle)| {
+                vehicle.shifts.iter().flat_map(move |shift| {
+                    vec![
+                        (LocationEntry::Vehicle, index, shift.start.postcode.as_str()),
+                        (LocationEntry::Vehicle, index, shift.end.postcode.as_str()),
+                    ]
+                })
+            });
+        let jobs = self.plan.jobs.iter().enumerate().flat_map(|(index, job)| {
+            job.deliveries
+                .iter()
+                .flat_map(|delivery| delivery.places.iter())
+                .map(move |place| (LocationEntry::Job, index, place.postcode.as_str()))
+        });
+        vehicles.chain(jobs).collect()
+    }
+
+    /// Looks every distinct postcode of the request up at once, returning the coordinates of every
+    /// postcode and every entry of the request with a postcode that couldn't be resolved.
+    pub fn resolve_locations(&self) -> (Locations, Vec<UnresolvedLocation>) {
+        let entries = self.locations();
+        let postcodes: BTreeSet<String> = entries
+            .iter()
+            .map(|(_, _, postcode)| postcode.to_string())
+            .collect();
+        let postcodes: Vec<String> = postcodes.into_iter().collect();
+
+        let mut locations = Locations::new();
+        let mut errors = HashMap::new();
+        let resolved = geocoding::resolve_postcodes(&postcodes);
+        for (postcode, result) in postcodes.into_iter().zip(resolved) {
+            match result {
+                Ok(location) => {
+                    locations.insert(postcode, location);
+                }
+                Err(error) => {
+                    errors.insert(postcode, error);
+                }
+            }
+        }
+
+        let unresolved = entries
+            .into_iter()
+            .filter_map(|(entry, index, postcode)| {
+                Some(UnresolvedLocation {
+                    entry,
+                    index,
+                    error: errors.get(postcode)?.clone(),
+                    postcode: postcode.to_string(),
+                })
+            })
+            .collect();
+        (locations, unresolved)
+    }
+
+    /// The distinct travel profiles of the fleet, falling back to a car so that a problem without
+    /// vehicles still has a profile.
+    pub fn get_profiles(&self) -> Vec<TravelProfile> {
+        let profiles: BTreeSet<TravelProfile> = self
+            .fleet
+            .vehicles
+            .iter()
+            .map(|vehicle| vehicle.profile)
+            .collect();
+
+        if profiles.is_empty() {
+            vec![TravelProfile::default()]
+        } else {
+            profiles.into_iter().collect()
+        }
+    }
+
+    /// Every vehicle gets its own type, named by its index, as the solver needs type ids to be
+    /// unique.
+    fn build_vehicles(&self, locations: &Locations) -> Vec<VehicleType> {
+        self.fleet
+            .vehicles
+            .iter()
+            .enumerate()
+            .map(|(i, vehicle)| {
+                VehicleType {
+                    type_id: i.to_string(),
                     vehicle_ids: (*vehicle.vehicle_ids).to_owned(),
-                    profile: "car".to_string(), // TODO: enumerate the profile for the simple problem
-                    costs: vrp_pragmatic::format::problem::VehicleCosts {
+                    profile: vehicle.profile.name().to_string(),
+                    costs: VehicleCosts {
                         fixed: Option::from(vehicle.costs.fixed),
                         distance: vehicle.costs.distance,
                         time: vehicle.costs.time,
@@ -112,12 +247,11 @@ impl DetailedRequest {
                         .map(|shift| VehicleShift {
                             start: VehiclePlace {
                                 time: shift.start.time.to_string(),
-                                // TODO [#33]: utilise geocoding to get coordinates
-                                location: Location { lat: 0.0, lng: 0.0 },
+                                location: locations[&shift.start.postcode].clone(),
                             },
                             end: Option::from(VehiclePlace {
                                 time: shift.end.time.to_string(),
-                                location: Location { lat: 0.0, lng: 0.0 },
+                                location: locations[&shift.end.postcode].clone(),
                             }), //optional
                             breaks: None, // TODO: expose breaks
                             reloads: None,
@@ -128,106 +262,599 @@ impl DetailedRequest {
                     limits: None, // TODO: more on all of these
                 }
             })
-            .collect();
+            .collect()
+    }
+}
+
+impl Job {
+    /// Maps every delivery onto a pragmatic delivery task. The pragmatic format only has a
+    /// priority per job, so the most important (lowest) delivery priority is used.
+    fn build_job(&self, locations: &Locations) -> Result<ProblemJob, ProblemFail> {
+        let is_multi_job = self.deliveries.len() > 1;
+        let priorities = self
+            .deliveries
+            .iter()
+            .map(|delivery| {
+                i32::try_from(delivery.priority).map_err(|_| {
+                    ProblemFail::invalid(&format!(
+                        "The priority of job {} is out of range: {}",
+                        self.id, delivery.priority
+                    ))
+                })
+            })
+            .collect::<Result<Vec<i32>, _>>()?;
 
-        // TODO [#34]: explain single profile and provide valid inputs
-        let profile = Profile {
-            name: "car".to_string(),
-            profile_type: "car".to_string(),
-            speed: None,
-        }; //TODO: enum this
+        Ok(ProblemJob {
+            id: self.id.clone(),
+            pickups: None,
+            deliveries: Some(
+                self.deliveries
+                    .iter()
+                    .enumerate()
+                    .map(|(index, delivery)| delivery.build_task(index, is_multi_job, locations))
+                    .collect(),
+            ),
+            replacements: None,
+            services: None,
+            priority: priorities.into_iter().min(),
+            skills: None,
+        })
+    }
+}
 
-        vrp_pragmatic::format::problem::Problem {
-            plan: vrp_pragmatic::format::problem::Plan {
-                jobs: vec![],
-                relations: None,
-            },
-            fleet: vrp_pragmatic::format::problem::Fleet {
-                vehicles: fleet,
-                profiles: vec![profile],
+impl Delivery {
+    /// The delivery is tagged with its properties. Within a job of several deliveries every one
+    /// needs a tag of its own to be told apart, so those are tagged with their index first.
+    fn build_task(&self, index: usize, is_multi_job: bool, locations: &Locations) -> JobTask {
+        const DEFAULT_DEMAND: i32 = 1;
+
+        let properties = if self.properties.is_empty() {
+            None
+        } else {
+            Some(self.properties.join(","))
+        };
+
+        JobTask {
+            places: self
+                .places
+                .iter()
+                .map(|place| JobPlace {
+                    location: locations[&place.postcode].clone(),
+                    duration: place.duration,
+                    times: None,
+                })
+                .collect(),
+            demand: Some(vec![self.demand.unwrap_or(DEFAULT_DEMAND)]),
+            tag: match (is_multi_job, properties) {
+                (false, properties) => properties,
+                (true, None) => Some(index.to_string()),
+                (true, Some(properties)) => Some(format!("{}:{}", index, properties)),
             },
-            objectives: None,
-            config: None,
+        }
+    }
+}
+
+/// A full pragmatic problem, optionally carrying the routing matrices that should be used instead
+/// of the speed based approximation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PragmaticTrip {
+    #[serde(flatten)]
+    pub problem: problem::Problem,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrices: Option<Vec<problem::Matrix>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solver_config: Option<SolverConfig>,
+}
+
+/// Query parameters shared by the solver endpoints.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolveOptions {
+    pub format: Option<String>,
+    pub strict: Option<bool>,
+    pub provider: Option<MatrixProviderKind>,
+    pub directions: Option<bool>,
+    /// The distance formula of the geometric provider, overriding `GEOMETRIC_FORMULA`.
+    pub formula: Option<DistanceFormula>,
+    /// The detour factor of the geometric provider, overriding `GEOMETRIC_DETOUR_FACTOR`.
+    pub detour_factor: Option<f64>,
+}
+
+impl SolveOptions {
+    /// Unfeasible solutions are rejected unless `strict=false` is given, in which case they are
+    /// returned with their violations attached as warnings.
+    pub fn is_strict(&self) -> bool {
+        self.strict.unwrap_or(true)
+    }
+
+    pub fn response_format(&self, accept: Option<&str>) -> ResponseFormat {
+        ResponseFormat::negotiate(self.format.as_deref(), accept)
+    }
+
+    /// The routing matrix provider asked for with `provider=`, or the one this server is
+    /// configured with, computing geometric matrices with the `formula=` and `detourFactor=`
+    /// given.
+    pub fn matrix_provider(&self) -> Result<MatrixSource, MatrixFail> {
+        Ok(MatrixSource {
+            kind: self.provider.unwrap_or_else(MatrixProviderKind::from_env),
+            geometric: GeometricProvider::with_overrides(self.formula, self.detour_factor)?,
+        })
+    }
+
+    /// Tours are only routed along the roads with `directions=true`, as it takes a request to the
+    /// routing backend per tour.
+    pub fn wants_directions(&self) -> bool {
+        self.directions.unwrap_or(false)
+    }
+}
+
+/// Where a simple trip vehicle or job is, either a postcode to geocode, or coordinates given as a
+/// `"lat,lng"` string or a `{"lat", "lng"}` object that are used as they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SimpleLocation {
+    Text(String),
+    #[serde(deserialize_with = "deserialize_coordinates")]
+    Coordinates(Location),
+}
+
+/// Coordinates as they're given to a simple trip, refusing any other fields so that a job with
+/// coordinates and details of its own isn't mistaken for a bare location.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Coordinates {
+    lat: f64,
+    lng: f64,
+}
+
+fn deserialize_coordinates<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Location, D::Error> {
+    let Coordinates { lat, lng } = Coordinates::deserialize(deserializer)?;
+    Ok(Location { lat, lng })
+}
+
+impl Default for SimpleLocation {
+    fn default() -> Self {
+        SimpleLocation::Text(String::new())
+    }
+}
+
+impl SimpleLocation {
+    /// The coordinates when they're given instead of a postcode, checked to be in range.
+    pub fn coordinates(&self) -> Option<Result<Location, GeocodingFail>> {
+        match self {
+            SimpleLocation::Coordinates(location) => {
+                Some(geocoding::validate_location(location.clone()))
+            }
+            SimpleLocation::Text(text) => {
+                let mut parts = text.split(',');
+                let lat = parts.next()?.trim().parse().ok()?;
+                let lng = parts.next()?.trim().parse().ok()?;
+                if parts.next().is_some() {
+                    return None;
+                }
+                Some(geocoding::validate_location(Location { lat, lng }))
+            }
+        }
+    }
+
+    /// How the location is labelled, the postcode or coordinates as given or `lat,lng`.
+    pub fn label(&self) -> String {
+        match self {
+            SimpleLocation::Text(text) => text.clone(),
+            SimpleLocation::Coordinates(location) => format!("{},{}", location.lat, location.lng),
+        }
+    }
+}
+
+/// Checks the coordinates given as they are and looks every distinct postcode up at once,
+/// returning the coordinates of every location by its label and every entry with a location that
+/// couldn't be resolved.
+fn resolve_simple_locations(
+    entries: Vec<(LocationEntry, usize, &SimpleLocation)>,
+) -> (Locations, Vec<UnresolvedLocation>) {
+    let mut locations = Locations::new();
+    let mut errors = HashMap::new();
+    let mut postcodes = BTreeSet::new();
+    for (_, _, location) in &entries {
+        match location.coordinates() {
+            Some(Ok(coordinates)) => {
+                locations.insert(location.label(), coordinates);
+            }
+            Some(Err(error)) => {
+                errors.insert(location.label(), error);
+            }
+            None => {
+                postcodes.insert(location.label());
+            }
+        }
+    }
+
+    let postcodes: Vec<String> = postcodes.into_iter().collect();
+    let resolved = geocoding::resolve_postcodes(&postcodes);
+    for (postcode, result) in postcodes.into_iter().zip(resolved) {
+        match result {
+            Ok(location) => {
+                locations.insert(postcode, location);
+            }
+            Err(error) => {
+                errors.insert(postcode, error);
+            }
+        }
+    }
+
+    let unresolved = entries
+        .into_iter()
+        .filter_map(|(entry, index, location)| {
+            let postcode = location.label();
+            Some(UnresolvedLocation {
+                entry,
+                index,
+                error: errors.get(&postcode)?.clone(),
+                postcode,
+            })
+        })
+        .collect();
+    (locations, unresolved)
+}
+
+impl PartialEq<str> for SimpleLocation {
+    fn eq(&self, other: &str) -> bool {
+        matches!(self, SimpleLocation::Text(text) if text == other)
+    }
+}
+
+/// A simple trip job, either a bare location, a location carrying its own duration, time windows,
+/// demand and tag, or a parcel collected at one location and dropped off at another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SimpleJob {
+    Location(SimpleLocation),
+    Detailed(SimpleJobDetails),
+    PickupDelivery(SimplePickupDelivery),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimpleJobDetails {
+    #[serde(alias = "location")]
+    pub postcode: SimpleLocation,
+    /// Service duration in seconds, defaulting to the simple 120 minute task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// RFC3339 start and end pairs, any of which the job may be served in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<Vec<Vec<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub demand: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// One leg of a pickup and delivery job.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimpleStop {
+    #[serde(alias = "location")]
+    pub postcode: SimpleLocation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<Vec<Vec<String>>>,
+}
+
+/// A parcel that is loaded at the pickup and carried to the delivery by the same vehicle, taking
+/// up its demand of the vehicle's capacity in between. The demand defaults to a single parcel.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimplePickupDelivery {
+    pub pickup: SimpleStop,
+    pub delivery: SimpleStop,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub demand: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+impl SimpleJob {
+    const JOB_LENGTH: f64 = 120.0;
+    const PARCEL_DEMAND: i32 = 1;
+
+    pub fn locations(&self) -> Vec<&SimpleLocation> {
+        match self {
+            SimpleJob::Location(location) => vec![location],
+            SimpleJob::Detailed(details) => vec![&details.postcode],
+            SimpleJob::PickupDelivery(job) => vec![&job.pickup.postcode, &job.delivery.postcode],
+        }
+    }
+
+    /// Checks that every time window is a start and an end in RFC3339, the end not being before
+    /// the start, as the solver panics on any other window rather than refusing the problem.
+    fn check_times(&self, index: usize) -> Result<(), ProblemFail> {
+        let windows = match self {
+            SimpleJob::Location(_) => vec![],
+            SimpleJob::Detailed(details) => vec![&details.times],
+            SimpleJob::PickupDelivery(job) => vec![&job.pickup.times, &job.delivery.times],
+        };
+
+        windows
+            .into_iter()
+            .flatten()
+            .flatten()
+            .try_for_each(|window| match window.as_slice() {
+                [start, end] => match (
+                    DateTime::parse_from_rfc3339(start),
+                    DateTime::parse_from_rfc3339(end),
+                ) {
+                    (Ok(start), Ok(end)) if start <= end => Ok(()),
+                    (Ok(_), Ok(_)) => Err(ProblemFail::invalid(&format!(
+                        "Job {} has a time window ending before it starts: {:?}",
+                        index, window
+                    ))),
+                    _ => Err(ProblemFail::invalid(&format!(
+                        "Job {} has a time window that isn't in RFC3339: {:?}",
+                        index, window
+                    ))),
+                },
+                _ => Err(ProblemFail::invalid(&format!(
+                    "Job {} has a time window that isn't a start and an end: {:?}",
+                    index, window
+                ))),
+            })
+    }
+
+    fn build_place(
+        location: &Location,
+        duration: Option<f64>,
+        times: Option<Vec<Vec<String>>>,
+    ) -> JobPlace {
+        let default_duration = Duration::minutes(Self::JOB_LENGTH as i64).num_seconds() as f64;
+
+        JobPlace {
+            location: location.clone(),
+            duration: duration.unwrap_or(default_duration),
+            times,
+        }
+    }
+
+    fn build_stop_task(
+        stop: &SimpleStop,
+        locations: &Locations,
+        demand: i32,
+        tag: Option<String>,
+    ) -> Option<JobTask> {
+        Some(JobTask {
+            places: vec![Self::build_place(
+                locations.get(&stop.postcode.label())?,
+                stop.duration,
+                stop.times.clone(),
+            )],
+            demand: Some(vec![demand]),
+            tag,
+        })
+    }
+
+    /// Jobs carrying a demand are delivered from the vehicle's start, as the pragmatic format only
+    /// allows demand on pickups and deliveries, everything else is served in place. Jobs with a
+    /// postcode that wasn't resolved are left out.
+    fn build_job(&self, index: usize, locations: &Locations) -> Option<ProblemJob> {
+        let mut job = ProblemJob {
+            id: index.to_string(),
+            pickups: None,
+            deliveries: None,
+            replacements: None,
+            services: None,
+            priority: None,
+            skills: None,
+        };
+
+        match self {
+            SimpleJob::Location(location) => {
+                job.services = Some(vec![JobTask {
+                    places: vec![Self::build_place(
+                        locations.get(&location.label())?,
+                        None,
+                        None,
+                    )],
+                    demand: None,
+                    tag: Some(String::from("Simple 120 minute task")),
+                }]);
+            }
+            SimpleJob::Detailed(details) => {
+                let task = JobTask {
+                    places: vec![Self::build_place(
+                        locations.get(&details.postcode.label())?,
+                        details.duration,
+                        details.times.clone(),
+                    )],
+                    demand: details.demand.map(|demand| vec![demand]),
+                    tag: details.tag.clone(),
+                };
+                if task.demand.is_some() {
+                    job.deliveries = Some(vec![task]);
+                } else {
+                    job.services = Some(vec![task]);
+                }
+            }
+            SimpleJob::PickupDelivery(parcel) => {
+                let demand = parcel.demand.unwrap_or(Self::PARCEL_DEMAND);
+                job.pickups = Some(vec![Self::build_stop_task(
+                    &parcel.pickup,
+                    locations,
+                    demand,
+                    parcel.tag.clone(),
+                )?]);
+                job.deliveries = Some(vec![Self::build_stop_task(
+                    &parcel.delivery,
+                    locations,
+                    demand,
+                    parcel.tag.clone(),
+                )?]);
+            }
+        }
+
+        Some(job)
+    }
+}
+
+/// A simple trip vehicle, either a bare postcode driven by car or a postcode with the travel
+/// profile the vehicle is routed with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SimpleVehicle {
+    Location(SimpleLocation),
+    Detailed(SimpleVehicleDetails),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimpleVehicleDetails {
+    #[serde(alias = "location")]
+    pub postcode: SimpleLocation,
+    #[serde(default)]
+    pub profile: TravelProfile,
+}
+
+impl SimpleVehicle {
+    pub fn location(&self) -> &SimpleLocation {
+        match self {
+            SimpleVehicle::Location(location) => location,
+            SimpleVehicle::Detailed(details) => &details.postcode,
+        }
+    }
+
+    pub fn profile(&self) -> TravelProfile {
+        match self {
+            SimpleVehicle::Location(_) => TravelProfile::default(),
+            SimpleVehicle::Detailed(details) => details.profile,
         }
     }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SimpleTrip {
-    pub coordinate_vehicles: Vec<String>,
-    pub coordinate_jobs: Vec<String>,
+    pub coordinate_vehicles: Vec<SimpleVehicle>,
+    pub coordinate_jobs: Vec<SimpleJob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solver_config: Option<SolverConfig>,
+    /// Departure times to build time dependent matrices for, on the matrix endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traffic: Option<TrafficOptions>,
+    /// Leaves vehicles and jobs whose postcodes can't be geocoded out of the problem instead of
+    /// rejecting the trip, reporting them alongside the solution.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drop_unresolved: Option<bool>,
 }
 
 impl SimpleTrip {
-    pub async fn convert_to_internal_problem(&self) -> problem::Problem {
-        problem::Problem {
+    /// Traffic is only taken into account through routing matrices, which are only built on the
+    /// matrix endpoint, so anywhere else it's refused rather than silently ignored.
+    pub fn check_without_traffic(&self) -> Result<(), MatrixFail> {
+        match self.traffic {
+            Some(_) => Err(MatrixFail::invalid(
+                "Traffic is only supported when solving with routing matrices, on /routing/solver/simple/matrix",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Geocodes the trip into a pragmatic problem, failing with the first job with a malformed
+    /// time window, or with every location that couldn't be resolved unless those are to be
+    /// dropped, in which case they're returned with the problem.
+    pub async fn convert_to_internal_problem(
+        &self,
+    ) -> Result<(problem::Problem, Vec<UnresolvedLocation>), Rejection> {
+        self.coordinate_jobs
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, job)| job.check_times(index))
+            .map_err(reject::custom)?;
+
+        let (locations, unresolved) = self.resolve_locations();
+        if !unresolved.is_empty() && !self.drop_unresolved.unwrap_or(false) {
+            return Err(reject::custom(GeocodingFail::unresolved(unresolved)));
+        }
+
+        let problem = problem::Problem {
             plan: ProblemPlan {
-                jobs: self.build_jobs(),
+                jobs: self.build_jobs(&locations),
                 relations: None,
             },
             fleet: ProblemFleet {
-                vehicles: self.build_vehicles(),
-                profiles: vec![self.get_simple_profile()],
+                vehicles: self.build_vehicles(&locations),
+                profiles: self
+                    .get_profiles()
+                    .into_iter()
+                    .map(TravelProfile::to_pragmatic_profile)
+                    .collect(),
             },
             objectives: None,
             config: None,
-        }
+        };
+        Ok((problem, unresolved))
     }
 
-    fn get_simple_profile(&self) -> Profile {
-        const FOURTY_MPH_IN_METRES_PER_SECOND: f64 = 17.0;
-        let normal_car = "normal_car".to_string();
-        let car_type = "car".to_string();
-        Profile {
-            name: normal_car,
-            profile_type: car_type,
-            speed: Some(FOURTY_MPH_IN_METRES_PER_SECOND), // TODO: average 40mph
-        }
+    /// Every location of the fleet and then of the jobs, in the order they're given, along with
+    /// the vehicle or job it belongs to.
+    pub fn locations(&self) -> Vec<(LocationEntry, usize, &SimpleLocation)> {
+        let vehicles = self
+            .coordinate_vehicles
+            .iter()
+            .enumerate()
+            .map(|(index, vehicle)| (LocationEntry::Vehicle, index, vehicle.location()));
+        let jobs = self
+            .coordinate_jobs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, job)| {
+                job.locations()
+                    .into_iter()
+                    .map(move |location| (LocationEntry::Job, index, location))
+            });
+        vehicles.chain(jobs).collect()
     }
 
-    fn build_jobs(&self) -> Vec<ProblemJob> {
-        const JOB_LENGTH: f64 = 120.0;
+    /// Checks the coordinates given as they are and looks every distinct postcode of the trip up
+    /// at once, returning the coordinates of every location by its label and every entry of the
+    /// trip with a location that couldn't be resolved.
+    pub fn resolve_locations(&self) -> (Locations, Vec<UnresolvedLocation>) {
+        resolve_simple_locations(self.locations())
+    }
+
+    /// The distinct travel profiles of the fleet, falling back to a car so that a problem without
+    /// vehicles still has a profile.
+    pub fn get_profiles(&self) -> Vec<TravelProfile> {
+        let profiles: BTreeSet<TravelProfile> = self
+            .coordinate_vehicles
+            .iter()
+            .map(SimpleVehicle::profile)
+            .collect();
 
+        if profiles.is_empty() {
+            vec![TravelProfile::default()]
+        } else {
+            profiles.into_iter().collect()
+        }
+    }
+
+    fn build_jobs(&self, locations: &Locations) -> Vec<ProblemJob> {
         self.coordinate_jobs
-            .to_vec()
-            .into_par_iter()
+            .par_iter()
             .enumerate()
-            .map(|(index, location)| {
-                ProblemJob {
-                    id: index.to_string(),
-                    // TODO [#21]: potentially switch on the type of job to decide whether its a pickup, delivery or service
-                    pickups: None,
-                    deliveries: None,
-                    replacements: None,
-                    services: Some(vec![JobTask {
-                        places: vec![JobPlace {
-                            location: geocoding::lookup_coordinates(location),
-                            // TODO [#23]: add constants to this duration
-                            // TODO [#24]: parameterise duration for the simple type as an optional query parameter
-                            duration: Duration::minutes(JOB_LENGTH as i64).num_seconds() as f64,
-                            times: None,
-                        }],
-                        demand: None,
-                        tag: Some(String::from("Simple 120 minute task")),
-                    }]),
-                    priority: None,
-                    skills: None,
-                }
-            })
+            .filter_map(|(index, job)| job.build_job(index, locations))
             .collect()
     }
 
-    fn build_vehicles(&self) -> Vec<VehicleType> {
+    /// Vehicles keep the index they were given as their id, even when others are left out.
+    fn build_vehicles(&self, locations: &Locations) -> Vec<VehicleType> {
         self.coordinate_vehicles
-            .to_vec()
-            .into_par_iter()
+            .par_iter()
             .enumerate()
-            .map(|(i, vehicle)| {
-                VehicleType {
+            .filter_map(|(i, vehicle)| {
+                let location = locations.get(&vehicle.location().label())?;
+                Some(VehicleType {
                     type_id: i.to_string(),
                     // TODO [#35]: type_id: "car".to_string(), for some reason this needs to be unique?
                     vehicle_ids: vec![i.to_string()],
-                    profile: "normal_car".to_string(),
+                    profile: vehicle.profile().name().to_string(),
                     costs: VehicleCosts {
                         fixed: Some(22.0),
                         distance: 0.0002,
@@ -236,7 +863,7 @@ impl SimpleTrip {
                     shifts: vec![VehicleShift {
                         start: VehiclePlace {
                             time: chrono::Utc::now().to_rfc3339(),
-                            location: geocoding::lookup_coordinates(vehicle),
+                            location: location.clone(),
                         },
                         end: None,
                         breaks: None,
@@ -245,25 +872,276 @@ impl SimpleTrip {
                     capacity: vec![5],
                     skills: None,
                     limits: None,
-                }
+                })
             })
             .collect()
     }
 }
 
+/// Travel times between points without solving anything. Without destinations the matrix is
+/// all-to-all between the origins.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixRequest {
+    pub origins: Vec<SimpleLocation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destinations: Option<Vec<SimpleLocation>>,
+    #[serde(default)]
+    pub profile: TravelProfile,
+}
+
+impl MatrixRequest {
+    pub fn get_destinations(&self) -> &[SimpleLocation] {
+        self.destinations.as_deref().unwrap_or(&self.origins)
+    }
+
+    /// Every point of the origins and then of the destinations, when they're given apart.
+    fn points(&self) -> Vec<(LocationEntry, usize, &SimpleLocation)> {
+        let origins = self
+            .origins
+            .iter()
+            .enumerate()
+            .map(|(index, point)| (LocationEntry::Origin, index, point));
+        let destinations = self
+            .destinations
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, point)| (LocationEntry::Destination, index, point));
+        origins.chain(destinations).collect()
+    }
+
+    /// Looks every distinct postcode up at once, returning the coordinates of the origins and of
+    /// the destinations, or every point that couldn't be resolved.
+    pub fn resolve_locations(&self) -> Result<(Vec<Location>, Vec<Location>), GeocodingFail> {
+        let (locations, unresolved) = resolve_simple_locations(self.points());
+        if !unresolved.is_empty() {
+            return Err(GeocodingFail::unresolved(unresolved));
+        }
+
+        let get_locations = |points: &[SimpleLocation]| -> Vec<Location> {
+            points
+                .iter()
+                .map(|point| locations[&point.label()].clone())
+                .collect()
+        };
+        Ok((
+            get_locations(&self.origins),
+            get_locations(self.get_destinations()),
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::request::{DetailedRequest, SimpleTrip};
+    use crate::geocoding::{GeocodingErrorCode, LocationEntry};
+    use crate::geometric::DistanceFormula;
+    use crate::matrix::MatrixProviderKind;
+    use crate::profile::TravelProfile;
+    use crate::request::{
+        DetailedRequest, Job, MatrixRequest, PragmaticTrip, SimpleJob, SimpleLocation, SimpleTrip,
+        SolveOptions,
+    };
+    use std::collections::HashMap;
+    use vrp_pragmatic::format::Location;
 
     #[test]
     fn test_deserialise_and_convert() {
         let request = r#"{"coordinate_vehicles": ["BS1 3AA", "BA2 1AA"],"coordinate_jobs": ["BS6 666", "BS7 777"]}"#;
 
         let obj: SimpleTrip = serde_json::from_str(request).unwrap();
-        assert_eq!(obj.coordinate_vehicles.first().unwrap(), "BS1 3AA");
-        assert_eq!(obj.coordinate_vehicles[1], "BA2 1AA");
-        assert_eq!(obj.coordinate_jobs.first().unwrap(), "BS6 666");
-        assert_eq!(obj.coordinate_jobs[1], "BS7 777");
+        assert_eq!(
+            obj.coordinate_vehicles.first().unwrap().location(),
+            "BS1 3AA"
+        );
+        assert_eq!(obj.coordinate_vehicles[1].location(), "BA2 1AA");
+        assert_eq!(
+            obj.coordinate_jobs.first().unwrap().locations(),
+            vec!["BS6 666"]
+        );
+        assert_eq!(obj.coordinate_jobs[1].locations(), vec!["BS7 777"]);
+        assert_eq!(obj.solver_config, None);
+    }
+
+    #[test]
+    fn test_resolve_invalid_postcodes() {
+        let request = r#"{"coordinate_vehicles": ["BS6 666"],"coordinate_jobs": ["BS7 777", {"pickup": {"postcode": "BS6 666"}, "delivery": {"postcode": ""}}]}"#;
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        assert_eq!(obj.locations().len(), 4);
+
+        let (locations, unresolved) = obj.resolve_locations();
+        assert!(locations.is_empty());
+        let entries: Vec<(LocationEntry, usize)> = unresolved
+            .iter()
+            .map(|location| (location.entry, location.index))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (LocationEntry::Vehicle, 0),
+                (LocationEntry::Job, 0),
+                (LocationEntry::Job, 1),
+                (LocationEntry::Job, 1)
+            ]
+        );
+        assert!(unresolved
+            .iter()
+            .all(|location| location.error.code() == GeocodingErrorCode::InvalidPostcode));
+    }
+
+    #[test]
+    fn test_parse_coordinate_locations() {
+        let request = r#"{"coordinate_vehicles": ["51.455691, -2.588186", {"location": {"lat": 51.449516, "lng": -2.58}}],"coordinate_jobs": [{"lat": 51.45, "lng": -2.6}, "BS1 3AA", {"pickup": {"postcode": "51.4,-2.5"}, "delivery": {"location": "BS1 1AA"}}]}"#;
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+
+        let coordinates: Vec<Option<Location>> = obj
+            .locations()
+            .iter()
+            .map(|(_, _, location)| location.coordinates().map(Result::unwrap))
+            .collect();
+        let expected = vec![
+            Some((51.455691, -2.588186)),
+            Some((51.449516, -2.58)),
+            Some((51.45, -2.6)),
+            None,
+            Some((51.4, -2.5)),
+            None,
+        ];
+        let expected: Vec<Option<Location>> = expected
+            .into_iter()
+            .map(|location| location.map(|(lat, lng)| Location { lat, lng }))
+            .collect();
+        assert_eq!(coordinates, expected);
+        assert_eq!(obj.coordinate_jobs[0].locations()[0].label(), "51.45,-2.6");
+    }
+
+    #[test]
+    fn test_coordinates_with_details() {
+        let job: Result<SimpleJob, _> =
+            serde_json::from_str(r#"{"lat": 51.45, "lng": -2.6, "duration": 300.0}"#);
+        assert!(job.is_err());
+
+        let job: SimpleJob =
+            serde_json::from_str(r#"{"location": {"lat": 51.45, "lng": -2.6}, "duration": 300.0}"#)
+                .unwrap();
+        match job {
+            SimpleJob::Detailed(details) => assert_eq!(details.duration, Some(300.0)),
+            job => panic!("Expected a detailed job but got {:?}", job),
+        }
+    }
+
+    #[test]
+    fn test_check_job_times() {
+        let job: SimpleJob = serde_json::from_str(
+            r#"{"postcode": "BS1 1AA", "times": [["2020-06-01T09:00:00Z", "2020-06-01T12:00:00Z"]]}"#,
+        )
+        .unwrap();
+        assert!(job.check_times(0).is_ok());
+
+        for times in &[
+            r#"[["2020-06-01T09:00:00Z"]]"#,
+            r#"[["2020-06-01T09:00:00Z", "2020-06-01T12:00:00Z", "2020-06-01T15:00:00Z"]]"#,
+            r#"[["2020-06-01 09:00", "2020-06-01T12:00:00Z"]]"#,
+            r#"[["2020-06-01T12:00:00Z", "2020-06-01T09:00:00Z"]]"#,
+        ] {
+            let job: SimpleJob = serde_json::from_str(&format!(
+                r#"{{"pickup": {{"postcode": "BS1 1AA"}}, "delivery": {{"postcode": "BS1 3AA", "times": {}}}}}"#,
+                times
+            ))
+            .unwrap();
+            assert!(job.check_times(0).is_err(), "{}", times);
+        }
+    }
+
+    #[test]
+    fn test_resolve_invalid_coordinates() {
+        let request = r#"{"coordinate_vehicles": ["51.455691,-2.588186"],"coordinate_jobs": ["91.0,-2.5", {"lat": 51.45, "lng": 181.0}]}"#;
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+
+        let (locations, unresolved) = obj.resolve_locations();
+        assert_eq!(
+            locations.get("51.455691,-2.588186"),
+            Some(&Location {
+                lat: 51.455691,
+                lng: -2.588186
+            })
+        );
+        let entries: Vec<(usize, &str)> = unresolved
+            .iter()
+            .map(|location| (location.index, location.postcode.as_str()))
+            .collect();
+        assert_eq!(entries, vec![(0, "91.0,-2.5"), (1, "51.45,181")]);
+        assert!(unresolved
+            .iter()
+            .all(|location| location.error.code() == GeocodingErrorCode::InvalidCoordinates));
+    }
+
+    #[test]
+    fn test_build_without_unresolved_locations() {
+        let request = r#"{"coordinate_vehicles": ["BS1 3AA", "BS6 666"],"coordinate_jobs": ["BS1 1AA", "BS7 777", {"pickup": {"postcode": "BS1 3AA"}, "delivery": {"postcode": "BS7 777"}}]}"#;
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        let mut locations = HashMap::new();
+        for (postcode, lat) in &[("BS1 3AA", 51.455691), ("BS1 1AA", 51.449516)] {
+            locations.insert(
+                postcode.to_string(),
+                Location {
+                    lat: *lat,
+                    lng: -2.58,
+                },
+            );
+        }
+
+        let vehicles = obj.build_vehicles(&locations);
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles[0].vehicle_ids, vec!["0".to_string()]);
+
+        let jobs = obj.build_jobs(&locations);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, "0");
+        assert_eq!(
+            jobs[0].services.as_ref().unwrap()[0].places[0].location.lat,
+            51.449516
+        );
+    }
+
+    #[test]
+    fn test_check_without_traffic() {
+        let request = r#"{"coordinate_vehicles": ["BS1 3AA"],"coordinate_jobs": ["BS6 666"]}"#;
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        assert!(obj.check_without_traffic().is_ok());
+
+        let request = r#"{"coordinate_vehicles": ["BS1 3AA"],"coordinate_jobs": ["BS6 666"], "traffic": {"departureTimes": ["2020-06-01T08:00:00Z"]}}"#;
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        assert!(obj.check_without_traffic().is_err());
+    }
+
+    #[test]
+    fn test_deserialise_solver_config() {
+        let request = r#"{"coordinate_vehicles": ["BS1 3AA"],"coordinate_jobs": ["BS6 666"], "solver_config": {"maxTime": 2, "maxGenerations": 50}}"#;
+
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        let config = obj.solver_config.unwrap();
+        assert_eq!(config.max_time, Some(2));
+        assert_eq!(config.max_generations, Some(50));
+    }
+
+    #[test]
+    fn test_geometric_solve_options() {
+        let options = SolveOptions {
+            provider: Some(MatrixProviderKind::Geometric),
+            formula: Some(DistanceFormula::Vincenty),
+            detour_factor: Some(1.2),
+            ..SolveOptions::default()
+        };
+        let source = options.matrix_provider().unwrap();
+        assert_eq!(source.kind, MatrixProviderKind::Geometric);
+        assert_eq!(source.geometric.formula, DistanceFormula::Vincenty);
+        assert_eq!(source.geometric.detour_factor, 1.2);
+
+        let options = SolveOptions {
+            detour_factor: Some(0.8),
+            ..SolveOptions::default()
+        };
+        assert!(options.matrix_provider().is_err());
     }
 
     #[test]
@@ -272,14 +1150,14 @@ mod tests {
 
         let obj: SimpleTrip = serde_json::from_str(request).unwrap();
 
-        let vehicles = obj.build_vehicles();
+        let vehicles = obj.build_vehicles(&obj.resolve_locations().0);
 
         assert_eq!(
             vehicles.first().unwrap().vehicle_ids.first().unwrap(),
             &0.to_string()
         );
         assert_eq!(vehicles.first().unwrap().type_id, 0.to_string());
-        assert_eq!(vehicles.first().unwrap().profile, "normal_car".to_string());
+        assert_eq!(vehicles.first().unwrap().profile, "car".to_string());
         assert_eq!(vehicles.first().unwrap().costs.fixed, Some(22.0));
         assert_eq!(vehicles.first().unwrap().costs.distance, 0.0002);
         assert_eq!(vehicles.first().unwrap().costs.time, 0.004806);
@@ -311,7 +1189,7 @@ mod tests {
 
         assert_eq!(vehicles[1].vehicle_ids.first().unwrap(), &1.to_string());
         assert_eq!(vehicles[1].type_id, 1.to_string());
-        assert_eq!(vehicles[1].profile, "normal_car".to_string());
+        assert_eq!(vehicles[1].profile, "car".to_string());
         assert_eq!(vehicles[1].costs.fixed, Some(22.0));
         assert_eq!(vehicles[1].costs.distance, 0.0002);
         assert_eq!(vehicles[1].costs.time, 0.004806);
@@ -326,13 +1204,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_deserialise_vehicle_profiles() {
+        let request = r#"{"coordinate_vehicles": ["BS1 3AA", {"postcode": "BA2 1AA", "profile": "bicycle"}, {"postcode": "BS6 666"}],"coordinate_jobs": []}"#;
+
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        assert_eq!(obj.coordinate_vehicles[0].profile(), TravelProfile::Car);
+        assert_eq!(obj.coordinate_vehicles[1].profile(), TravelProfile::Bicycle);
+        assert_eq!(obj.coordinate_vehicles[1].location(), "BA2 1AA");
+        assert_eq!(obj.coordinate_vehicles[2].profile(), TravelProfile::Car);
+        assert_eq!(
+            obj.get_profiles(),
+            vec![TravelProfile::Car, TravelProfile::Bicycle]
+        );
+
+        let obj = SimpleTrip::default();
+        assert_eq!(obj.get_profiles(), vec![TravelProfile::Car]);
+    }
+
     #[test]
     fn test_deserialise_and_build_jobs() {
         let request = r#"{"coordinate_vehicles": ["BS13AA", "BA21AA"],"coordinate_jobs": ["BS11AA", "BA21AA"]}"#;
 
         let obj: SimpleTrip = serde_json::from_str(request).unwrap();
 
-        let jobs = obj.build_jobs();
+        let jobs = obj.build_jobs(&obj.resolve_locations().0);
 
         let service = jobs[0].services.clone().unwrap();
         assert_eq!(jobs[0].id, 0.to_string());
@@ -347,6 +1243,49 @@ mod tests {
         assert_eq!(service[0].places[0].duration, 7200.0);
     }
 
+    #[test]
+    fn test_deserialise_and_build_detailed_jobs() {
+        let request = r#"{"coordinate_vehicles": ["BS13AA"],"coordinate_jobs": ["BS11AA", {"postcode": "BA21AA", "duration": 300.0, "times": [["2020-06-01T09:00:00Z", "2020-06-01T12:00:00Z"], ["2020-06-01T14:00:00Z", "2020-06-01T17:00:00Z"]], "demand": 2, "tag": "install"}]}"#;
+
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        assert_eq!(
+            obj.coordinate_jobs[0],
+            SimpleJob::Location(SimpleLocation::Text("BS11AA".to_string()))
+        );
+        assert_eq!(obj.coordinate_jobs[1].locations(), vec!["BA21AA"]);
+
+        let jobs = obj.build_jobs(&obj.resolve_locations().0);
+        assert!(jobs[0].deliveries.is_none());
+        assert_eq!(jobs[0].services.clone().unwrap()[0].demand, None);
+
+        assert!(jobs[1].services.is_none());
+        let delivery = jobs[1].deliveries.clone().unwrap();
+        assert_eq!(delivery[0].places[0].location.lat, 51.375932);
+        assert_eq!(delivery[0].places[0].duration, 300.0);
+        assert_eq!(delivery[0].places[0].times.as_ref().unwrap().len(), 2);
+        assert_eq!(delivery[0].demand, Some(vec![2]));
+        assert_eq!(delivery[0].tag, Some("install".to_string()));
+    }
+
+    #[test]
+    fn test_deserialise_and_build_pickup_delivery_jobs() {
+        let request = r#"{"coordinate_vehicles": ["BS13AA"],"coordinate_jobs": [{"pickup": {"postcode": "BS11AA", "duration": 60.0}, "delivery": {"postcode": "BA21AA"}, "demand": 3}]}"#;
+
+        let obj: SimpleTrip = serde_json::from_str(request).unwrap();
+        assert_eq!(obj.coordinate_jobs[0].locations(), vec!["BS11AA", "BA21AA"]);
+
+        let jobs = obj.build_jobs(&obj.resolve_locations().0);
+        assert!(jobs[0].services.is_none());
+        let pickup = jobs[0].pickups.clone().unwrap();
+        assert_eq!(pickup[0].places[0].location.lat, 51.449516);
+        assert_eq!(pickup[0].places[0].duration, 60.0);
+        assert_eq!(pickup[0].demand, Some(vec![3]));
+        let delivery = jobs[0].deliveries.clone().unwrap();
+        assert_eq!(delivery[0].places[0].location.lat, 51.375932);
+        assert_eq!(delivery[0].places[0].duration, 7200.0);
+        assert_eq!(delivery[0].demand, Some(vec![3]));
+    }
+
     #[test]
     fn test_convert_to_internal_problem() {
         let request = r#"
@@ -359,7 +1298,7 @@ mod tests {
           {
             "places": [
               {
-                "postcode": "",
+                "postcode": "BS1 1AA",
                 "duration": 240.0
               }
             ],
@@ -385,11 +1324,11 @@ mod tests {
           {
             "start": {
               "time": "2019-07-04T09:00:00Z",
-              "postcode": ""
+              "postcode": "BS1 3AA"
             },
             "end": {
               "time": "2019-07-04T18:00:00Z",
-              "postcode": ""
+              "postcode": "BA2 1AA"
             }
           }
         ],
@@ -402,7 +1341,240 @@ mod tests {
             serde_json::from_str(request).expect("Unable to serialise request");
         assert_eq!(obj.fleet.vehicles[0].costs.fixed, 22.0 as f64);
 
-        let problem = obj.convert_to_internal_problem();
-        assert_eq!(problem.fleet.profiles.first().unwrap().name, "car")
+        let problem = obj.convert_to_internal_problem().unwrap();
+        assert_eq!(problem.fleet.profiles.first().unwrap().name, "car");
+        assert_eq!(problem.fleet.vehicles[0].type_id, "0");
+
+        let shift = &problem.fleet.vehicles[0].shifts[0];
+        assert_eq!(shift.start.location.lat, 51.455691);
+        assert_eq!(shift.start.location.lng, -2.586119);
+        let end = shift.end.as_ref().unwrap();
+        assert_eq!(end.location.lat, 51.375932);
+        assert_eq!(end.location.lng, -2.382291);
+
+        let job = &problem.plan.jobs[0];
+        assert_eq!(job.id, "multi_job1");
+        assert_eq!(job.priority, Some(2));
+        assert!(job.services.is_none());
+        let delivery = &job.deliveries.as_ref().unwrap()[0];
+        assert_eq!(delivery.places[0].location.lat, 51.449516);
+        assert_eq!(delivery.places[0].location.lng, -2.57837);
+        assert_eq!(delivery.places[0].duration, 240.0);
+        assert_eq!(delivery.demand, Some(vec![1]));
+        assert_eq!(delivery.tag, Some("d1".to_string()));
+    }
+
+    #[test]
+    fn test_resolve_invalid_detailed_postcodes() {
+        let request = r#"{"plan": {"jobs": [{"id": "job1", "deliveries": [{"places": [{"postcode": "", "duration": 60.0}], "priority": 1, "properties": []}]}]}, "fleet": {"vehicles": [{"vehicleIds": ["vehicle_1"], "costs": {"fixed": 22.0, "distance": 0.0002, "time": 0.004806}, "shifts": [{"start": {"time": "2019-07-04T09:00:00Z", "postcode": "BS6 666"}, "end": {"time": "2019-07-04T18:00:00Z", "postcode": "BS6 666"}}], "capacity": 10}]}}"#;
+        let obj: DetailedRequest = serde_json::from_str(request).unwrap();
+        assert_eq!(obj.locations().len(), 3);
+
+        let (locations, unresolved) = obj.resolve_locations();
+        assert!(locations.is_empty());
+        let entries: Vec<(LocationEntry, usize)> = unresolved
+            .iter()
+            .map(|location| (location.entry, location.index))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (LocationEntry::Vehicle, 0),
+                (LocationEntry::Vehicle, 0),
+                (LocationEntry::Job, 0)
+            ]
+        );
+        assert!(obj.convert_to_internal_problem().is_err());
+    }
+
+    #[test]
+    fn test_build_detailed_multi_job_tags() {
+        let request = r#"{"id": "job1", "deliveries": [{"places": [{"postcode": "BS1 1AA", "duration": 60.0}], "priority": 2, "properties": []}, {"places": [{"postcode": "BS1 1AA", "duration": 60.0}], "priority": 1, "properties": ["fragile"]}]}"#;
+        let job: Job = serde_json::from_str(request).unwrap();
+        let mut locations = HashMap::new();
+        locations.insert(
+            "BS1 1AA".to_string(),
+            Location {
+                lat: 51.449516,
+                lng: -2.57837,
+            },
+        );
+
+        let job = job.build_job(&locations).unwrap();
+        assert_eq!(job.priority, Some(1));
+        let tags: Vec<Option<String>> = job
+            .deliveries
+            .unwrap()
+            .into_iter()
+            .map(|delivery| delivery.tag)
+            .collect();
+        assert_eq!(
+            tags,
+            vec![Some("0".to_string()), Some("1:fragile".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_build_detailed_job_priority_out_of_range() {
+        let request = r#"{"id": "job1", "deliveries": [{"places": [{"postcode": "BS1 1AA", "duration": 60.0}], "priority": 4294967296, "properties": []}]}"#;
+        let job: Job = serde_json::from_str(request).unwrap();
+        let mut locations = HashMap::new();
+        locations.insert(
+            "BS1 1AA".to_string(),
+            Location {
+                lat: 51.449516,
+                lng: -2.57837,
+            },
+        );
+
+        assert!(job.build_job(&locations).is_err());
+    }
+
+    #[test]
+    fn test_detailed_vehicle_profiles() {
+        let request = r#"{"plan": {"jobs": []}, "fleet": {"vehicles": [
+            {"vehicleIds": ["a"], "costs": {"fixed": 0.0, "distance": 0.0, "time": 0.0}, "shifts": [], "capacity": 1, "profile": "bicycle"},
+            {"vehicleIds": ["b"], "costs": {"fixed": 0.0, "distance": 0.0, "time": 0.0}, "shifts": [], "capacity": 1}
+        ]}}"#;
+        let obj: DetailedRequest = serde_json::from_str(request).unwrap();
+        assert_eq!(obj.fleet.vehicles[0].profile, TravelProfile::Bicycle);
+        assert_eq!(obj.fleet.vehicles[1].profile, TravelProfile::Car);
+        assert_eq!(
+            obj.get_profiles(),
+            vec![TravelProfile::Car, TravelProfile::Bicycle]
+        );
+
+        let vehicles = obj.build_vehicles(&HashMap::new());
+        assert_eq!(vehicles[0].profile, "bicycle");
+        assert_eq!(vehicles[1].profile, "car");
+    }
+
+    #[test]
+    fn test_deserialise_pragmatic_trip() {
+        let request = r#"
+{
+  "plan": {
+    "jobs": [
+      {
+        "id": "job1",
+        "services": [
+          {
+            "places": [
+              {
+                "location": { "lat": 51.449516, "lng": -2.57837 },
+                "duration": 180.0
+              }
+            ]
+          }
+        ]
+      }
+    ]
+  },
+  "fleet": {
+    "vehicles": [
+      {
+        "typeId": "vehicle",
+        "vehicleIds": ["vehicle_1"],
+        "profile": "car",
+        "costs": { "fixed": 22.0, "distance": 0.0002, "time": 0.004806 },
+        "shifts": [
+          {
+            "start": {
+              "time": "2019-07-04T09:00:00Z",
+              "location": { "lat": 51.455691, "lng": -2.586119 }
+            }
+          }
+        ],
+        "capacity": [10]
+      }
+    ],
+    "profiles": [{ "name": "car", "type": "car" }]
+  },
+  "matrices": [
+    {
+      "profile": "car",
+      "travelTimes": [0, 120, 120, 0],
+      "distances": [0, 900, 900, 0]
+    }
+  ],
+  "solverConfig": { "maxTime": 600 }
+}"#;
+        let trip: PragmaticTrip = serde_json::from_str(request).unwrap();
+        assert_eq!(trip.problem.plan.jobs[0].id, "job1");
+        assert_eq!(trip.problem.fleet.vehicles[0].vehicle_ids[0], "vehicle_1");
+
+        let matrices = trip.matrices.unwrap();
+        assert_eq!(matrices[0].travel_times, vec![0, 120, 120, 0]);
+        assert_eq!(matrices[0].profile, Some("car".to_string()));
+        assert_eq!(trip.solver_config.unwrap().max_time, Some(600));
+    }
+
+    #[test]
+    fn test_deserialise_pragmatic_trip_without_matrices() {
+        let request = r#"{"plan": {"jobs": []}, "fleet": {"vehicles": [], "profiles": []}}"#;
+        let trip: PragmaticTrip = serde_json::from_str(request).unwrap();
+        assert!(trip.matrices.is_none());
+        assert!(trip.problem.objectives.is_none());
+    }
+
+    #[test]
+    fn test_deserialise_matrix_request() {
+        let request =
+            r#"{"origins": ["BS1 3AA", {"lat": 51.375932, "lng": -2.382291}], "profile": "van"}"#;
+        let request: MatrixRequest = serde_json::from_str(request).unwrap();
+        assert_eq!(
+            request.origins[0],
+            SimpleLocation::Text("BS1 3AA".to_string())
+        );
+        assert_eq!(request.origins[1].label(), "51.375932,-2.382291");
+        assert_eq!(request.profile, TravelProfile::Van);
+        assert_eq!(request.get_destinations(), request.origins.as_slice());
+
+        let request = r#"{"origins": ["BS1 3AA"], "destinations": ["BA2 1AA", "BS1 1AA"]}"#;
+        let request: MatrixRequest = serde_json::from_str(request).unwrap();
+        assert_eq!(request.get_destinations().len(), 2);
+        assert_eq!(request.profile, TravelProfile::Car);
+    }
+
+    #[test]
+    fn test_resolve_invalid_matrix_points() {
+        let request = r#"{"origins": ["", {"lat": 51.375932, "lng": -2.382291}], "destinations": [{"lat": 91.0, "lng": -2.382291}]}"#;
+        let request: MatrixRequest = serde_json::from_str(request).unwrap();
+
+        let error = request.resolve_locations().unwrap_err();
+        let error = serde_json::to_value(&error).unwrap();
+        let unresolved: Vec<(&str, u64, &str)> = error["unresolved"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| {
+                (
+                    location["entry"].as_str().unwrap(),
+                    location["index"].as_u64().unwrap(),
+                    location["error"]["code"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            unresolved,
+            vec![
+                ("origin", 0, "INVALID_POSTCODE"),
+                ("destination", 0, "INVALID_COORDINATES")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_matrix_coordinates() {
+        let request =
+            r#"{"origins": [{"lat": 51.375932, "lng": -2.382291}, "51.455691,-2.588186"]}"#;
+        let request: MatrixRequest = serde_json::from_str(request).unwrap();
+        let (origins, destinations) = request.resolve_locations().unwrap();
+        assert_eq!(origins[0].lat, 51.375932);
+        assert_eq!(origins[1].lng, -2.588186);
+        assert_eq!(origins, destinations);
+
+        let request = r#"{"origins": [{"lat": 51.375932, "lng": -2.382291, "duration": 60}]}"#;
+        assert!(serde_json::from_str::<MatrixRequest>(request).is_err());
     }
 }
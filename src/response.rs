@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::solution::{
+    Extras, Solution, Statistic, UnassignedJob, UnassignedJobReason,
+};
+use warp::http::header::CONTENT_TYPE;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::Reply;
+
+use crate::directions::{RoutedTour, TourRoute};
+use crate::feasibility::Violation;
+use crate::geocoding::{LocationEntry, UnresolvedLocation};
+use crate::geojson;
+use crate::matrix::RoutingMatrix;
+use crate::matrix_cache::CacheStats;
+use crate::profile::TravelProfile;
+use crate::solver::SolverConfig;
+
+/// A pragmatic solution whose tours carry their road geometry, when directions were asked for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutedSolution {
+    pub statistic: Statistic,
+    pub tours: Vec<RoutedTour>,
+    pub unassigned: Vec<UnassignedJob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Extras>,
+}
+
+impl From<Solution> for RoutedSolution {
+    fn from(solution: Solution) -> Self {
+        RoutedSolution {
+            statistic: solution.statistic,
+            tours: solution.tours.into_iter().map(RoutedTour::from).collect(),
+            unassigned: solution.unassigned,
+            extras: solution.extras,
+        }
+    }
+}
+
+/// A pragmatic solution along with the solver settings that were actually used to find it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolveResponse {
+    #[serde(flatten)]
+    pub solution: RoutedSolution,
+    pub solver_config: SolverConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Violation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix_cache: Option<CacheStats>,
+    /// Vehicles and jobs that were left out of the problem as their postcodes couldn't be
+    /// geocoded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<UnresolvedLocation>,
+}
+
+/// Kept clear of the reason codes the solver gives unassigned jobs itself.
+pub const UNRESOLVED_LOCATION_CODE: i32 = 200;
+
+impl SolveResponse {
+    pub fn new(solution: Solution, solver_config: SolverConfig) -> SolveResponse {
+        SolveResponse {
+            solution: RoutedSolution::from(solution),
+            solver_config,
+            warnings: vec![],
+            matrix_cache: None,
+            unresolved: vec![],
+        }
+    }
+
+    pub fn with_warnings(self, warnings: Vec<Violation>) -> SolveResponse {
+        SolveResponse { warnings, ..self }
+    }
+
+    pub fn with_matrix_cache(self, matrix_cache: Option<CacheStats>) -> SolveResponse {
+        SolveResponse {
+            matrix_cache,
+            ..self
+        }
+    }
+
+    /// Attaches the road geometry of every tour to it.
+    pub fn with_routes(mut self, routes: Vec<TourRoute>) -> SolveResponse {
+        self.solution.tours = self
+            .solution
+            .tours
+            .into_iter()
+            .map(|tour| tour.with_route(&routes))
+            .collect();
+        self
+    }
+
+    /// Reports the dropped locations, with the jobs among them unassigned in the solution.
+    pub fn with_unresolved(mut self, unresolved: Vec<UnresolvedLocation>) -> SolveResponse {
+        for location in &unresolved {
+            let job_id = location.index.to_string();
+            let is_unassigned = self
+                .solution
+                .unassigned
+                .iter()
+                .any(|job| job.job_id == job_id);
+            if location.entry == LocationEntry::Job && !is_unassigned {
+                self.solution.unassigned.push(UnassignedJob {
+                    job_id,
+                    reasons: vec![UnassignedJobReason {
+                        code: UNRESOLVED_LOCATION_CODE,
+                        description: format!(
+                            "unable to geocode {}: {}",
+                            location.postcode,
+                            location.error.message()
+                        ),
+                    }],
+                });
+            }
+        }
+        SolveResponse { unresolved, ..self }
+    }
+
+    pub fn into_reply(self, format: ResponseFormat) -> Response {
+        format.reply(&self.solution.tours, &self)
+    }
+}
+
+/// Why a request failed, replied as `{"error": {"code", "message"}}` along with its status.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: &str, message: &str) -> ErrorResponse {
+        ErrorResponse {
+            code: code.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn into_reply(self, status: StatusCode) -> Response {
+        warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": self })),
+            status,
+        )
+        .into_response()
+    }
+}
+
+/// Durations in seconds and distances in metres between labelled points, with `null` wherever no
+/// route was found.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatrixResponse {
+    pub profile: TravelProfile,
+    pub origins: Vec<String>,
+    pub destinations: Vec<String>,
+    #[serde(flatten)]
+    pub matrix: RoutingMatrix,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix_cache: Option<CacheStats>,
+}
+
+/// Rejects an unfeasible solution, listing every constraint it violates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViolationsResponse {
+    pub violations: Vec<Violation>,
+}
+
+impl ViolationsResponse {
+    pub fn new(violations: Vec<Violation>) -> ViolationsResponse {
+        ViolationsResponse { violations }
+    }
+
+    pub fn into_reply(self) -> Response {
+        warp::reply::with_status(warp::reply::json(&self), StatusCode::UNPROCESSABLE_ENTITY)
+            .into_response()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseFormat {
+    Json,
+    GeoJson,
+}
+
+impl ResponseFormat {
+    /// Picks GeoJSON when either the `format` query parameter asks for it or the `Accept` header
+    /// prefers it, otherwise falls back to the pragmatic JSON solution.
+    pub fn negotiate(format: Option<&str>, accept: Option<&str>) -> ResponseFormat {
+        match format {
+            Some(format) if format.eq_ignore_ascii_case("geojson") => ResponseFormat::GeoJson,
+            Some(_) => ResponseFormat::Json,
+            None => match accept {
+                Some(accept) if prefers_geo_json(accept) => ResponseFormat::GeoJson,
+                _ => ResponseFormat::Json,
+            },
+        }
+    }
+
+    /// Replies with `body` as JSON, or with `tours` as a GeoJSON feature collection drawn along
+    /// their routes.
+    pub fn reply<T: Serialize>(self, tours: &[RoutedTour], body: &T) -> Response {
+        match self {
+            ResponseFormat::Json => warp::reply::json(body).into_response(),
+            ResponseFormat::GeoJson => warp::reply::with_header(
+                warp::reply::json(&geojson::build_feature_collection(tours)),
+                CONTENT_TYPE,
+                geojson::GEO_JSON_CONTENT_TYPE,
+            )
+            .into_response(),
+        }
+    }
+}
+
+/// Whether an `Accept` header names GeoJSON, with a quality at least as high as that of JSON.
+fn prefers_geo_json(accept: &str) -> bool {
+    let accept = accept.to_ascii_lowercase();
+    match get_quality(&accept, geojson::GEO_JSON_CONTENT_TYPE) {
+        Some((quality, true)) => {
+            let json = get_quality(&accept, "application/json").map_or(0.0, |(quality, _)| quality);
+            quality > 0.0 && quality >= json
+        }
+        _ => false,
+    }
+}
+
+/// The quality given to a media type by the most specific range of an `Accept` header matching
+/// it, along with whether that range names the media type rather than a wildcard.
+fn get_quality(accept: &str, media_type: &str) -> Option<(f32, bool)> {
+    let wildcard = format!("{}/*", media_type.split('/').next()?);
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let range = params.next()?.trim();
+            let specificity = if range == media_type {
+                2
+            } else if range == wildcard {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                return None;
+            };
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+            Some((specificity, quality))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(specificity, quality)| (quality, specificity == 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geocoding::{GeocodingErrorCode, GeocodingFail};
+    use std::io::BufReader;
+    use vrp_pragmatic::format::solution::deserialize_solution;
+
+    fn get_unresolved(entry: LocationEntry, index: usize) -> UnresolvedLocation {
+        UnresolvedLocation {
+            entry,
+            index,
+            postcode: "BS6 666".to_string(),
+            error: GeocodingFail::new(GeocodingErrorCode::InvalidPostcode, "Not a postcode"),
+        }
+    }
+
+    #[test]
+    fn test_with_unresolved() {
+        let solution = r#"{"statistic": {"cost": 0.0, "distance": 0, "duration": 0, "times": {"driving": 0, "serving": 0, "waiting": 0, "break": 0}}, "tours": [], "unassigned": []}"#;
+        let solution = deserialize_solution(BufReader::new(solution.as_bytes())).unwrap();
+
+        let response = SolveResponse::new(solution, SolverConfig::default()).with_unresolved(vec![
+            get_unresolved(LocationEntry::Vehicle, 0),
+            get_unresolved(LocationEntry::Job, 2),
+            get_unresolved(LocationEntry::Job, 2),
+        ]);
+
+        assert_eq!(response.unresolved.len(), 3);
+        assert_eq!(response.solution.unassigned.len(), 1);
+        let unassigned = &response.solution.unassigned[0];
+        assert_eq!(unassigned.job_id, "2");
+        assert_eq!(unassigned.reasons[0].code, UNRESOLVED_LOCATION_CODE);
+    }
+
+    #[test]
+    fn test_with_routes() {
+        let solution = r#"{"statistic": {"cost": 0.0, "distance": 0, "duration": 0, "times": {"driving": 0, "serving": 0, "waiting": 0, "break": 0}}, "tours": [{"vehicleId": "0", "typeId": "0", "shiftIndex": 0, "stops": [], "statistic": {"cost": 0.0, "distance": 0, "duration": 0, "times": {"driving": 0, "serving": 0, "waiting": 0, "break": 0}}}, {"vehicleId": "1", "typeId": "1", "shiftIndex": 0, "stops": [], "statistic": {"cost": 0.0, "distance": 0, "duration": 0, "times": {"driving": 0, "serving": 0, "waiting": 0, "break": 0}}}], "unassigned": []}"#;
+        let solution = deserialize_solution(BufReader::new(solution.as_bytes())).unwrap();
+        let route = TourRoute {
+            vehicle_id: "1".to_string(),
+            geometry: "_p~iF~ps|U".to_string(),
+            ..TourRoute::default()
+        };
+
+        let response =
+            SolveResponse::new(solution, SolverConfig::default()).with_routes(vec![route]);
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(json.get("routes").is_none());
+        assert!(json["tours"][0].get("route").is_none());
+        assert_eq!(json["tours"][1]["vehicleId"], "1");
+        assert_eq!(json["tours"][1]["route"]["geometry"], "_p~iF~ps|U");
+        assert!(json["tours"][1]["route"].get("vehicleId").is_none());
+    }
+
+    #[test]
+    fn test_error_reply() {
+        let error = ErrorResponse::new("INVALID_PROBLEM", "No jobs");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({"code": "INVALID_PROBLEM", "message": "No jobs"})
+        );
+        let reply = error.into_reply(StatusCode::BAD_REQUEST);
+        assert_eq!(reply.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_negotiate_format() {
+        assert_eq!(ResponseFormat::negotiate(None, None), ResponseFormat::Json);
+        assert_eq!(
+            ResponseFormat::negotiate(Some("geojson"), None),
+            ResponseFormat::GeoJson
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(None, Some("application/geo+json")),
+            ResponseFormat::GeoJson
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(None, Some("application/json")),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            ResponseFormat::negotiate(Some("json"), Some("application/geo+json")),
+            ResponseFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_negotiate_format_by_quality() {
+        for (accept, format) in &[
+            ("application/geo+json;q=0", ResponseFormat::Json),
+            ("application/geo+json; q=0.0, */*", ResponseFormat::Json),
+            (
+                "application/json, application/geo+json;q=0.5",
+                ResponseFormat::Json,
+            ),
+            (
+                "application/json;q=0.5, application/geo+json",
+                ResponseFormat::GeoJson,
+            ),
+            (
+                "application/geo+json, application/json",
+                ResponseFormat::GeoJson,
+            ),
+            (
+                "APPLICATION/GEO+JSON;Q=0.9, application/*;q=0.8",
+                ResponseFormat::GeoJson,
+            ),
+            ("*/*", ResponseFormat::Json),
+        ] {
+            assert_eq!(ResponseFormat::negotiate(None, Some(accept)), *format);
+        }
+    }
+}
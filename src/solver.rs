@@ -1,25 +1,25 @@
-use std::io::{BufRead, BufReader, BufWriter};
+use std::cmp::min;
+use std::io::{BufReader, BufWriter};
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+use warp::reject;
+use warp::reply::Response;
+
 use vrp_core::models::{Problem as CoreProblem, Solution as CoreSolution};
 use vrp_core::solver::{Builder, Metrics, Solver};
-use vrp_pragmatic::format::problem::{deserialize_problem, Problem};
+use vrp_pragmatic::checker::CheckerContext;
+use vrp_pragmatic::format::problem::{deserialize_problem, Matrix, PragmaticProblem, Problem};
 use vrp_pragmatic::format::solution::{deserialize_solution, PragmaticSolution, Solution};
+use vrp_pragmatic::format::FormatError;
+
+use crate::response::ErrorResponse;
 
 pub fn get_pragmatic_problem(problem_text: &str) -> Problem {
     deserialize_problem(BufReader::new(problem_text.as_bytes())).unwrap()
 }
 
-pub fn get_pragmatic_solution(
-    problem: &CoreProblem,
-    solution: &CoreSolution,
-) -> (Solution, String) {
-    (
-        build_pragmatic_solution(&problem, &solution),
-        build_geo_json(&problem, &solution),
-    )
-}
-
 pub fn build_pragmatic_solution(problem: &CoreProblem, solution: &CoreSolution) -> Solution {
     let mut buffer = String::new();
     // TODO [#36]: don't be unsafe
@@ -31,26 +31,247 @@ pub fn build_pragmatic_solution(problem: &CoreProblem, solution: &CoreSolution)
     deserialize_solution(BufReader::new(buffer.as_bytes())).expect("cannot deserialize solution")
 }
 
-pub fn build_geo_json(problem: &CoreProblem, solution: &CoreSolution) -> String {
-    // TODO [#36]: don't be unsafe
-    let mut buffer_geojson = String::new();
-    let writer = unsafe { BufWriter::new(buffer_geojson.as_mut_vec()) };
-    solution
-        .write_geo_json(&problem, writer)
-        .expect("Unable to write geojson");
+pub const DEFAULT_MAX_GENERATIONS: usize = 100;
+pub const DEFAULT_MAX_TIME_SECONDS: usize = 90;
+/// The population settings the solver falls back to itself.
+pub const DEFAULT_INITIAL_SIZE: usize = 2;
+pub const DEFAULT_POPULATION_SIZE: usize = 4;
+pub const DEFAULT_ELITE_SIZE: usize = 2;
+
+/// Termination and search settings for a single solve. Anything left out falls back to the
+/// defaults of this service, or of the solver itself for the population settings.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolverConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_time: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_generations: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_variation: Option<CostVariation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub population: Option<PopulationConfig>,
+}
+
+/// Stops the search once the cost variation over the last `sample` generations drops below
+/// `threshold`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostVariation {
+    pub sample: usize,
+    pub threshold: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PopulationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub population_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offspring_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elite_size: Option<usize>,
+}
+
+/// Server side caps applied to every requested `SolverConfig`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolverLimits {
+    pub max_time: usize,
+    pub max_generations: usize,
+    pub max_population_size: usize,
+}
+
+impl Default for SolverLimits {
+    fn default() -> Self {
+        SolverLimits {
+            max_time: 600,
+            max_generations: 3000,
+            max_population_size: 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SolverConfigFail {
+    message: String,
+}
+
+impl reject::Reject for SolverConfigFail {}
+
+impl SolverConfigFail {
+    pub fn new(message: &str) -> SolverConfigFail {
+        SolverConfigFail {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn to_reply(&self) -> Response {
+        ErrorResponse::new("INVALID_SOLVER_CONFIG", &self.message)
+            .into_reply(StatusCode::BAD_REQUEST)
+    }
+}
+
+/// The solver couldn't be built for a problem, or stopped without a solution, such as by
+/// panicking.
+#[derive(Debug)]
+pub struct SolverFail {
+    message: String,
+    /// Whether the solver refused the problem or config it was given, rather than failing while
+    /// solving.
+    invalid: bool,
+}
+
+impl reject::Reject for SolverFail {}
+
+impl SolverFail {
+    pub fn new(message: &str) -> SolverFail {
+        SolverFail {
+            message: message.to_string(),
+            invalid: false,
+        }
+    }
+
+    pub fn invalid(message: &str) -> SolverFail {
+        SolverFail {
+            invalid: true,
+            ..SolverFail::new(message)
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn to_reply(&self) -> Response {
+        if self.invalid {
+            ErrorResponse::new("SOLVER_REJECTED", &self.message).into_reply(StatusCode::BAD_REQUEST)
+        } else {
+            ErrorResponse::new("SOLVER_FAILED", &self.message)
+                .into_reply(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Why a pragmatic problem couldn't be solved.
+#[derive(Debug)]
+pub enum SolveError {
+    /// The problem couldn't be read.
+    Problem(Vec<FormatError>),
+    /// The solver couldn't be built for the problem.
+    Solver(SolverFail),
+}
+
+impl CostVariation {
+    /// The solver never stops on a threshold that isn't a positive number, or on an empty sample.
+    fn validate(&self) -> Result<(), SolverConfigFail> {
+        if self.sample < 1 {
+            Err(SolverConfigFail::new(
+                "costVariation sample has to be at least 1 generation",
+            ))
+        } else if !self.threshold.is_finite() || self.threshold <= 0.0 {
+            Err(SolverConfigFail::new(&format!(
+                "costVariation threshold has to be a positive number, not {}",
+                self.threshold
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl PopulationConfig {
+    /// Checks the sizes against each other, including those the solver falls back to, as the
+    /// solver panics on sizes that don't fit together.
+    fn validate(&self) -> Result<(), SolverConfigFail> {
+        let initial_size = self.initial_size.unwrap_or(DEFAULT_INITIAL_SIZE);
+        let population_size = self.population_size.unwrap_or(DEFAULT_POPULATION_SIZE);
+        let elite_size = self.elite_size.unwrap_or(DEFAULT_ELITE_SIZE);
 
-    BufReader::new(buffer_geojson.as_bytes())
-        .lines()
-        .map(|l| l.unwrap())
-        .collect()
+        if initial_size < 1 || initial_size > population_size {
+            Err(SolverConfigFail::new(&format!(
+                "initialSize has to be between 1 and the populationSize of {}, not {}",
+                population_size, initial_size
+            )))
+        } else if elite_size >= population_size {
+            Err(SolverConfigFail::new(&format!(
+                "eliteSize has to be less than the populationSize of {}, not {}",
+                population_size, elite_size
+            )))
+        } else {
+            Ok(())
+        }
+    }
 }
 
-pub fn create_solver(problem: Arc<CoreProblem>) -> Solver {
-    Builder::new(problem)
-        .with_max_generations(Some(100))
-        .with_max_time(Some(90))
+impl SolverConfig {
+    /// Fills in the defaults and clamps every setting to the given limits, returning the config
+    /// that will actually be used for solving, or why the solver couldn't use it.
+    pub fn resolve(&self, limits: &SolverLimits) -> Result<SolverConfig, SolverConfigFail> {
+        let cap_population =
+            |size: Option<usize>| size.map(|size| min(size, limits.max_population_size));
+
+        let config = SolverConfig {
+            max_time: Some(min(
+                self.max_time.unwrap_or(DEFAULT_MAX_TIME_SECONDS),
+                limits.max_time,
+            )),
+            max_generations: Some(min(
+                self.max_generations.unwrap_or(DEFAULT_MAX_GENERATIONS),
+                limits.max_generations,
+            )),
+            cost_variation: self.cost_variation.clone(),
+            population: self.population.as_ref().map(|population| PopulationConfig {
+                initial_size: cap_population(population.initial_size),
+                population_size: cap_population(population.population_size),
+                offspring_size: cap_population(population.offspring_size),
+                elite_size: cap_population(population.elite_size),
+            }),
+        };
+        if let Some(cost_variation) = &config.cost_variation {
+            cost_variation.validate()?;
+        }
+        if let Some(population) = &config.population {
+            population.validate()?;
+        }
+        Ok(config)
+    }
+}
+
+pub fn create_solver(
+    problem: Arc<CoreProblem>,
+    config: &SolverConfig,
+) -> Result<Solver, SolverFail> {
+    let mut builder = Builder::new(problem)
+        .with_max_generations(config.max_generations)
+        .with_max_time(config.max_time)
+        .with_cost_variation(
+            config
+                .cost_variation
+                .as_ref()
+                .map(|variation| (variation.sample, variation.threshold)),
+        );
+
+    if let Some(population) = &config.population {
+        if let Some(size) = population.initial_size {
+            builder = builder.with_initial_size(size);
+        }
+        if let Some(size) = population.population_size {
+            builder = builder.with_population_size(size);
+        }
+        if let Some(size) = population.offspring_size {
+            builder = builder.with_offspring_size(size);
+        }
+        if let Some(size) = population.elite_size {
+            builder = builder.with_elite_size(size);
+        }
+    }
+
+    builder
         .build()
-        .unwrap_or_else(|err| panic!("cannot build solver, error: {}", err))
+        .map_err(|err| SolverFail::invalid(&format!("Unable to build the solver: {}", err)))
 }
 
 pub fn solve_problem(solver: Solver) -> (CoreSolution, f64, Option<Metrics>) {
@@ -59,6 +280,35 @@ pub fn solve_problem(solver: Solver) -> (CoreSolution, f64, Option<Metrics>) {
         .unwrap_or_else(|err| panic!("cannot solve problem, error: {}", err))
 }
 
+pub fn get_core_problem(
+    problem: Problem,
+    matrices: Option<Vec<Matrix>>,
+) -> Result<Arc<CoreProblem>, Vec<FormatError>> {
+    if let Some(matrices) = matrices {
+        (problem, matrices).read_pragmatic()
+    } else {
+        problem.read_pragmatic()
+    }
+    .map(Arc::new)
+}
+
+/// Solves a pragmatic problem and wraps the pragmatic solution in a `CheckerContext` so that the
+/// feasibility of the solution can be validated against the original problem and matrices.
+pub fn solve(
+    problem: Problem,
+    matrices: Option<Vec<Matrix>>,
+    config: &SolverConfig,
+) -> Result<CheckerContext, SolveError> {
+    let core_problem =
+        get_core_problem(problem.clone(), matrices.clone()).map_err(SolveError::Problem)?;
+    let solver = create_solver(core_problem.clone(), config).map_err(SolveError::Solver)?;
+
+    let (solution, _, _) = solve_problem(solver);
+    let solution = build_pragmatic_solution(&core_problem, &solution);
+
+    Ok(CheckerContext::new(problem, matrices, solution))
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -67,7 +317,139 @@ mod tests {
     use vrp_pragmatic::format::problem::{PragmaticProblem, Problem};
 
     use crate::solver;
-    use crate::solver::{create_solver, get_pragmatic_problem, solve_problem};
+    use crate::solver::{
+        create_solver, get_pragmatic_problem, solve_problem, CostVariation, PopulationConfig,
+        SolverConfig, SolverLimits, DEFAULT_MAX_GENERATIONS, DEFAULT_MAX_TIME_SECONDS,
+    };
+
+    #[test]
+    fn test_resolve_default_solver_config() {
+        let config = SolverConfig::default()
+            .resolve(&SolverLimits::default())
+            .unwrap();
+        assert_eq!(config.max_time, Some(DEFAULT_MAX_TIME_SECONDS));
+        assert_eq!(config.max_generations, Some(DEFAULT_MAX_GENERATIONS));
+        assert_eq!(config.cost_variation, None);
+        assert_eq!(config.population, None);
+    }
+
+    #[test]
+    fn test_resolve_solver_config_within_limits() {
+        let config = SolverConfig {
+            max_time: Some(2),
+            max_generations: Some(50),
+            cost_variation: Some(CostVariation {
+                sample: 200,
+                threshold: 0.01,
+            }),
+            population: None,
+        };
+        let resolved = config.resolve(&SolverLimits::default()).unwrap();
+        assert_eq!(resolved.max_time, Some(2));
+        assert_eq!(resolved.max_generations, Some(50));
+        assert_eq!(resolved.cost_variation, config.cost_variation);
+    }
+
+    #[test]
+    fn test_resolve_solver_config_caps_to_limits() {
+        let config = SolverConfig {
+            max_time: Some(6000),
+            max_generations: Some(1_000_000),
+            cost_variation: None,
+            population: Some(PopulationConfig {
+                initial_size: Some(2),
+                population_size: Some(64),
+                offspring_size: None,
+                elite_size: None,
+            }),
+        };
+        let limits = SolverLimits {
+            max_time: 300,
+            max_generations: 2000,
+            max_population_size: 8,
+        };
+        let resolved = config.resolve(&limits).unwrap();
+        assert_eq!(resolved.max_time, Some(300));
+        assert_eq!(resolved.max_generations, Some(2000));
+
+        let population = resolved.population.unwrap();
+        assert_eq!(population.initial_size, Some(2));
+        assert_eq!(population.population_size, Some(8));
+        assert_eq!(population.offspring_size, None);
+    }
+
+    #[test]
+    fn test_resolve_invalid_population() {
+        let with_population = |population: PopulationConfig| SolverConfig {
+            population: Some(population),
+            ..SolverConfig::default()
+        };
+        for population in &[
+            PopulationConfig {
+                initial_size: Some(8),
+                ..PopulationConfig::default()
+            },
+            PopulationConfig {
+                initial_size: Some(0),
+                ..PopulationConfig::default()
+            },
+            PopulationConfig {
+                population_size: Some(4),
+                elite_size: Some(4),
+                ..PopulationConfig::default()
+            },
+            PopulationConfig {
+                population_size: Some(64),
+                elite_size: Some(32),
+                ..PopulationConfig::default()
+            },
+        ] {
+            assert!(with_population(population.clone())
+                .resolve(&SolverLimits::default())
+                .is_err());
+        }
+
+        let population = PopulationConfig {
+            initial_size: Some(8),
+            population_size: Some(8),
+            ..PopulationConfig::default()
+        };
+        assert!(with_population(population)
+            .resolve(&SolverLimits::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_resolve_invalid_cost_variation() {
+        let with_cost_variation = |sample: usize, threshold: f64| SolverConfig {
+            cost_variation: Some(CostVariation { sample, threshold }),
+            ..SolverConfig::default()
+        };
+        for (sample, threshold) in &[
+            (100, 0.0),
+            (100, -0.01),
+            (100, f64::NAN),
+            (100, f64::INFINITY),
+            (0, 0.01),
+        ] {
+            assert!(with_cost_variation(*sample, *threshold)
+                .resolve(&SolverLimits::default())
+                .is_err());
+        }
+
+        assert!(with_cost_variation(100, 0.01)
+            .resolve(&SolverLimits::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_deserialise_solver_config() {
+        let config = r#"{"maxTime": 2, "costVariation": {"sample": 100, "threshold": 0.05}}"#;
+        let config: SolverConfig = serde_json::from_str(config).unwrap();
+        assert_eq!(config.max_time, Some(2));
+        assert_eq!(config.max_generations, None);
+        assert_eq!(config.cost_variation.unwrap().sample, 100);
+    }
 
     #[test]
     fn test_pragmatic() {
@@ -1094,10 +1476,13 @@ mod tests {
 
         let problem = String::from(problem_text).read_pragmatic();
         let problem = Arc::new(problem.expect("Problem could not be marshalled to an arc"));
-        let (solution, _, _) = solve_problem(create_solver(problem.clone()));
+        let config = SolverConfig::default()
+            .resolve(&SolverLimits::default())
+            .unwrap();
+        let (solution, _, _) = solve_problem(create_solver(problem.clone(), &config).unwrap());
 
-        let (solution, _) =
-            solver::get_pragmatic_solution(&Arc::try_unwrap(problem).ok().unwrap(), &solution);
+        let solution =
+            solver::build_pragmatic_solution(&Arc::try_unwrap(problem).ok().unwrap(), &solution);
         let problem: Problem = get_pragmatic_problem(problem_text);
 
         // TODO [#26]: use matrices potentially
@@ -0,0 +1,344 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::Location;
+
+const EARTH_RADIUS_IN_METRES: f64 = 6_371_000.0;
+const DIMENSIONS: usize = 3;
+
+/// A postcode centroid.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostcodePoint {
+    pub postcode: String,
+    pub location: Location,
+}
+
+/// A postcode along with how far away it is in metres.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NearbyPostcode {
+    pub postcode: String,
+    pub location: Location,
+    pub distance: f64,
+}
+
+struct IndexedPoint {
+    position: [f64; DIMENSIONS],
+    point: PostcodePoint,
+}
+
+/// Places a location on the unit sphere, where the straight line distance between two points
+/// grows with the great-circle distance between them and doesn't wrap around at the antimeridian.
+fn to_position(location: &Location) -> [f64; DIMENSIONS] {
+    let (lat, lng) = (location.lat.to_radians(), location.lng.to_radians());
+    [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()]
+}
+
+fn get_squared_chord(from: &[f64; DIMENSIONS], to: &[f64; DIMENSIONS]) -> f64 {
+    from.iter()
+        .zip(to.iter())
+        .map(|(a, b)| (a - b).powi(2))
+        .sum()
+}
+
+fn chord_to_metres(squared_chord: f64) -> f64 {
+    2.0 * EARTH_RADIUS_IN_METRES * (squared_chord.sqrt() / 2.0).min(1.0).asin()
+}
+
+fn metres_to_squared_chord(metres: f64) -> f64 {
+    let angle = (metres / EARTH_RADIUS_IN_METRES).clamp(0.0, PI);
+    (2.0 * (angle / 2.0).sin()).powi(2)
+}
+
+/// A k-d tree over postcode centroids, stored implicitly with the median of every subtree in the
+/// middle of its range, so that the postcodes nearest to any point are found in logarithmic time.
+pub struct PostcodeIndex {
+    points: Vec<IndexedPoint>,
+}
+
+impl PostcodeIndex {
+    pub fn new(points: Vec<PostcodePoint>) -> PostcodeIndex {
+        let mut points: Vec<IndexedPoint> = points
+            .into_iter()
+            .map(|point| IndexedPoint {
+                position: to_position(&point.location),
+                point,
+            })
+            .collect();
+        build(&mut points, 0);
+        PostcodeIndex { points }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The postcode at a position of the index, which stays the same once the index is built.
+    pub fn get(&self, position: usize) -> Option<&PostcodePoint> {
+        self.points.get(position).map(|point| &point.point)
+    }
+
+    /// The postcode whose centroid is closest to the location.
+    pub fn nearest(&self, location: &Location) -> Option<NearbyPostcode> {
+        self.nearby(location, Some(1), None).into_iter().next()
+    }
+
+    /// Postcodes sorted by their distance from the location, limited to the `k` nearest, to those
+    /// within `radius` metres, or both.
+    pub fn nearby(
+        &self,
+        location: &Location,
+        k: Option<usize>,
+        radius: Option<f64>,
+    ) -> Vec<NearbyPostcode> {
+        let target = to_position(location);
+        let mut search = Search {
+            target,
+            limit: k.unwrap_or(usize::MAX),
+            max_squared_chord: radius.map_or(f64::INFINITY, metres_to_squared_chord),
+            found: BinaryHeap::new(),
+        };
+        if search.limit > 0 {
+            self.search(&mut search, 0, self.points.len(), 0);
+        }
+
+        search
+            .found
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| {
+                let point = &self.points[candidate.index].point;
+                NearbyPostcode {
+                    postcode: point.postcode.clone(),
+                    location: point.location.clone(),
+                    distance: chord_to_metres(candidate.squared_chord),
+                }
+            })
+            .collect()
+    }
+
+    fn search(&self, search: &mut Search, start: usize, end: usize, depth: usize) {
+        if start >= end {
+            return;
+        }
+
+        let middle = start + (end - start) / 2;
+        let position = &self.points[middle].position;
+        search.offer(middle, get_squared_chord(position, &search.target));
+
+        let axis = depth % DIMENSIONS;
+        let offset = search.target[axis] - position[axis];
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+
+        self.search(search, near.0, near.1, depth + 1);
+        // the other side can only hold anything closer if the splitting plane is
+        if offset.powi(2) <= search.get_bound() {
+            self.search(search, far.0, far.1, depth + 1);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    squared_chord: f64,
+    index: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.squared_chord
+            .partial_cmp(&other.squared_chord)
+            .unwrap_or(Ordering::Equal)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+/// The closest points found so far, with the furthest of them on top of the heap so that it can
+/// be swapped out as soon as something closer turns up.
+struct Search {
+    target: [f64; DIMENSIONS],
+    limit: usize,
+    max_squared_chord: f64,
+    found: BinaryHeap<Candidate>,
+}
+
+impl Search {
+    fn offer(&mut self, index: usize, squared_chord: f64) {
+        if squared_chord > self.max_squared_chord {
+            return;
+        }
+
+        let candidate = Candidate {
+            squared_chord,
+            index,
+        };
+        if self.found.len() < self.limit {
+            self.found.push(candidate);
+        } else if self
+            .found
+            .peek()
+            .is_some_and(|furthest| candidate < *furthest)
+        {
+            self.found.pop();
+            self.found.push(candidate);
+        }
+    }
+
+    /// How far away a point can be and still make it into the results.
+    fn get_bound(&self) -> f64 {
+        if self.found.len() < self.limit {
+            self.max_squared_chord
+        } else {
+            self.found
+                .peek()
+                .map_or(self.max_squared_chord, |furthest| furthest.squared_chord)
+        }
+    }
+}
+
+fn build(points: &mut [IndexedPoint], depth: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+
+    let axis = depth % DIMENSIONS;
+    let middle = points.len() / 2;
+    points.select_nth_unstable_by(middle, |a, b| {
+        a.position[axis]
+            .partial_cmp(&b.position[axis])
+            .expect("Postcode coordinates must be numbers")
+    });
+
+    let (left, right) = points.split_at_mut(middle);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometric::get_haversine_distance;
+
+    /// Spreads postcodes over Great Britain with a small linear congruential generator, so that
+    /// the tests are repeatable without a random number crate.
+    fn get_points(count: usize, seed: u64) -> Vec<PostcodePoint> {
+        let mut seed = seed;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..count)
+            .map(|index| PostcodePoint {
+                postcode: format!("PC{}", index),
+                location: Location {
+                    lat: 50.0 + next() * 8.0,
+                    lng: -6.0 + next() * 8.0,
+                },
+            })
+            .collect()
+    }
+
+    fn get_brute_force_nearest<'a>(
+        points: &'a [PostcodePoint],
+        location: &Location,
+    ) -> &'a PostcodePoint {
+        points
+            .iter()
+            .min_by(|a, b| {
+                get_haversine_distance(&a.location, location)
+                    .partial_cmp(&get_haversine_distance(&b.location, location))
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_nearest() {
+        let points = get_points(5000, 42);
+        let index = PostcodeIndex::new(points.clone());
+        assert_eq!(index.len(), 5000);
+
+        for target in get_points(200, 7).iter().map(|point| &point.location) {
+            let nearest = index.nearest(target).unwrap();
+            let expected = get_brute_force_nearest(&points, target);
+            assert_eq!(nearest.postcode, expected.postcode);
+            let distance = get_haversine_distance(&expected.location, target);
+            assert!((nearest.distance - distance).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_nearest_exact_match() {
+        let points = get_points(100, 42);
+        let index = PostcodeIndex::new(points.clone());
+        let nearest = index.nearest(&points[37].location).unwrap();
+        assert_eq!(nearest.postcode, "PC37");
+        assert_eq!(nearest.distance, 0.0);
+    }
+
+    #[test]
+    fn test_nearest_in_empty_index() {
+        let index = PostcodeIndex::new(vec![]);
+        assert!(index.is_empty());
+        assert_eq!(index.nearest(&Location { lat: 0.0, lng: 0.0 }), None);
+    }
+
+    #[test]
+    fn test_k_nearest() {
+        let points = get_points(5000, 42);
+        let index = PostcodeIndex::new(points.clone());
+        let target = &get_points(1, 7)[0].location;
+
+        let nearby = index.nearby(target, Some(10), None);
+        let mut expected: Vec<f64> = points
+            .iter()
+            .map(|point| get_haversine_distance(&point.location, target))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert_eq!(nearby.len(), 10);
+        for (found, expected) in nearby.iter().zip(expected.iter()) {
+            assert!((found.distance - expected).abs() < 0.01);
+        }
+        assert!(index.nearby(target, Some(0), None).is_empty());
+    }
+
+    #[test]
+    fn test_within_radius() {
+        let points = get_points(5000, 42);
+        let index = PostcodeIndex::new(points.clone());
+        let target = &get_points(1, 7)[0].location;
+
+        let nearby = index.nearby(target, None, Some(20_000.0));
+        let expected = points
+            .iter()
+            .filter(|point| get_haversine_distance(&point.location, target) <= 20_000.0)
+            .count();
+
+        assert!(expected > 0);
+        assert_eq!(nearby.len(), expected);
+        assert!(nearby
+            .windows(2)
+            .all(|pair| pair[0].distance <= pair[1].distance));
+        assert_eq!(index.nearby(target, Some(2), Some(20_000.0)).len(), 2);
+    }
+}
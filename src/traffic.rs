@@ -0,0 +1,229 @@
+use std::env;
+
+use chrono::{DateTime, FixedOffset, Timelike};
+use serde::{Deserialize, Serialize};
+use vrp_pragmatic::format::problem::Matrix as VrpMatrix;
+
+use crate::matrix::{MatrixFail, RoutingMatrix};
+use crate::profile::TravelProfile;
+
+const HOURS_IN_DAY: usize = 24;
+
+/// Departure times to build a matrix for each, so that the solver can pick the travel times that
+/// match when every leg is driven. Vehicles on the roads are sped up or slowed down by the
+/// multiplier of the hour they leave at, given in the offset of the departure time. A single
+/// departure time gives a single matrix in the traffic of that hour.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficOptions {
+    pub departure_times: Vec<String>,
+    /// Speeds relative to free flowing traffic for every hour of the day from midnight, so that
+    /// `0.5` doubles travel times. Falls back to `TRAFFIC_SPEED_MULTIPLIERS`, then free flow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_multipliers: Option<Vec<f64>>,
+}
+
+fn parse_speed_multipliers(multipliers: Vec<f64>) -> Option<Vec<f64>> {
+    if multipliers.len() == HOURS_IN_DAY && multipliers.iter().all(|speed| *speed > 0.0) {
+        Some(multipliers)
+    } else {
+        None
+    }
+}
+
+fn get_env_speed_multipliers() -> Option<Vec<f64>> {
+    let multipliers = env::var("TRAFFIC_SPEED_MULTIPLIERS").ok()?;
+    let multipliers = multipliers
+        .split(',')
+        .map(|speed| speed.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    parse_speed_multipliers(multipliers)
+}
+
+impl TrafficOptions {
+    /// The solver only interpolates between matrices of the same profile when there are at least
+    /// two of them, so a single departure time gives an ordinary matrix.
+    pub fn is_time_dependent(&self) -> bool {
+        self.departure_times.len() > 1
+    }
+
+    pub fn get_speed_multipliers(&self) -> Result<Vec<f64>, MatrixFail> {
+        match &self.speed_multipliers {
+            Some(multipliers) => parse_speed_multipliers(multipliers.clone()).ok_or_else(|| {
+                MatrixFail::invalid(
+                    "Speed multipliers need a positive value for every hour of the day",
+                )
+            }),
+            None => Ok(get_env_speed_multipliers().unwrap_or_else(|| vec![1.0; HOURS_IN_DAY])),
+        }
+    }
+
+    /// Every departure time, rejecting any that isn't in RFC 3339 or there being none at all.
+    pub fn get_departure_times(&self) -> Result<Vec<DateTime<FixedOffset>>, MatrixFail> {
+        if self.departure_times.is_empty() {
+            return Err(MatrixFail::invalid(
+                "Traffic needs at least one departure time",
+            ));
+        }
+
+        self.departure_times
+            .iter()
+            .map(|time| {
+                DateTime::parse_from_rfc3339(time).map_err(|_| {
+                    MatrixFail::invalid(&format!("Unable to read the departure time {}", time))
+                })
+            })
+            .collect()
+    }
+
+    /// The hour of the day of every departure time.
+    pub fn get_departure_hours(&self) -> Result<Vec<usize>, MatrixFail> {
+        Ok(self
+            .get_departure_times()?
+            .iter()
+            .map(|time| time.hour() as usize)
+            .collect())
+    }
+
+    /// Rejects departure times and speed multipliers that can't be used before anything is
+    /// routed.
+    pub fn validate(&self) -> Result<(), MatrixFail> {
+        self.get_departure_times()?;
+        self.get_speed_multipliers()?;
+        Ok(())
+    }
+
+    /// A matrix per departure time, with the durations of motorised profiles scaled by the speed
+    /// multiplier of the hour. Only several matrices are timestamped, as the solver refuses a
+    /// single timestamped matrix per profile.
+    pub fn to_vrp_matrices(
+        &self,
+        matrix: &RoutingMatrix,
+        profile: TravelProfile,
+    ) -> Result<Vec<VrpMatrix>, MatrixFail> {
+        let multipliers = self.get_speed_multipliers()?;
+        let hours = self.get_departure_hours()?;
+
+        Ok(self
+            .departure_times
+            .iter()
+            .zip(hours)
+            .map(|(time, hour)| {
+                let speed = if profile.is_motorised() {
+                    multipliers[hour]
+                } else {
+                    1.0
+                };
+                let mut timed = RoutingMatrix {
+                    durations: matrix
+                        .durations
+                        .iter()
+                        .map(|row| row.iter().map(|val| val.map(|val| val / speed)).collect())
+                        .collect(),
+                    distances: matrix.distances.clone(),
+                }
+                .to_vrp_matrix(profile);
+                if self.is_time_dependent() {
+                    timed.timestamp = Some(time.clone());
+                }
+                timed
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_options() -> TrafficOptions {
+        let mut speed_multipliers = vec![1.0; HOURS_IN_DAY];
+        speed_multipliers[8] = 0.5;
+        TrafficOptions {
+            departure_times: vec![
+                "2020-06-01T08:00:00+01:00".to_string(),
+                "2020-06-01T11:00:00+01:00".to_string(),
+            ],
+            speed_multipliers: Some(speed_multipliers),
+        }
+    }
+
+    fn get_matrix() -> RoutingMatrix {
+        RoutingMatrix {
+            durations: vec![vec![Some(0.0), Some(600.0)], vec![Some(610.0), None]],
+            distances: vec![vec![Some(0.0), Some(8000.0)], vec![Some(8100.0), None]],
+        }
+    }
+
+    #[test]
+    fn test_deserialise_traffic_options() {
+        let options: TrafficOptions =
+            serde_json::from_str(r#"{"departureTimes": ["2020-06-01T08:00:00Z"]}"#).unwrap();
+        assert!(!options.is_time_dependent());
+        assert_eq!(options.get_departure_hours().unwrap(), vec![8]);
+        assert!(get_options().is_time_dependent());
+    }
+
+    #[test]
+    fn test_get_speed_multipliers() {
+        let options = TrafficOptions {
+            speed_multipliers: Some(vec![1.0; 12]),
+            ..get_options()
+        };
+        assert!(options.get_speed_multipliers().is_err());
+        assert_eq!(get_options().get_speed_multipliers().unwrap()[8], 0.5);
+    }
+
+    #[test]
+    fn test_to_vrp_matrices() {
+        let matrices = get_options()
+            .to_vrp_matrices(&get_matrix(), TravelProfile::Car)
+            .unwrap();
+
+        assert_eq!(matrices.len(), 2);
+        assert_eq!(
+            matrices[0].timestamp,
+            Some("2020-06-01T08:00:00+01:00".to_string())
+        );
+        assert_eq!(matrices[0].travel_times, vec![0, 1200, 1220, 0]);
+        assert_eq!(matrices[1].travel_times, vec![0, 600, 610, 0]);
+        assert_eq!(matrices[0].distances, matrices[1].distances);
+        assert_eq!(matrices[0].error_codes, Some(vec![0, 0, 0, 1]));
+
+        let matrices = get_options()
+            .to_vrp_matrices(&get_matrix(), TravelProfile::Foot)
+            .unwrap();
+        assert_eq!(matrices[0].travel_times, matrices[1].travel_times);
+    }
+
+    #[test]
+    fn test_single_departure_time() {
+        let options = TrafficOptions {
+            departure_times: vec!["2020-06-01T08:00:00+01:00".to_string()],
+            ..get_options()
+        };
+        let matrices = options
+            .to_vrp_matrices(&get_matrix(), TravelProfile::Car)
+            .unwrap();
+
+        assert_eq!(matrices.len(), 1);
+        assert_eq!(matrices[0].timestamp, None);
+        assert_eq!(matrices[0].travel_times, vec![0, 1200, 1220, 0]);
+    }
+
+    #[test]
+    fn test_invalid_departure_time() {
+        let options = TrafficOptions {
+            departure_times: vec!["eight o'clock".to_string()],
+            speed_multipliers: None,
+        };
+        assert!(options.get_departure_hours().is_err());
+        assert!(options.validate().is_err());
+
+        let options = TrafficOptions {
+            departure_times: vec![],
+            speed_multipliers: None,
+        };
+        assert!(options.validate().is_err());
+    }
+}
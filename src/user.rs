@@ -1,7 +1,10 @@
 use crate::auth;
 use crate::redis_manager;
+use crate::response::ErrorResponse;
+use crate::solver::SolverLimits;
 use serde::export::fmt;
 use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
 use warp::reply::Response;
 use warp::{reject, Rejection, Reply};
 
@@ -13,6 +16,10 @@ pub struct User {
     simple_routes: Vec<String>,
 }
 
+/// Solver caps are kept apart from the user details, which users write themselves, so that they
+/// can only be set in redis by an admin.
+pub const SOLVER_LIMITS_TABLE_NAME: &str = "SOLVER_LIMITS";
+
 impl warp::reply::Reply for User {
     fn into_response(self) -> Response {
         Response::new(
@@ -46,6 +53,10 @@ impl UserFail {
             message: format!("Unable to find a user with id `{}`", id),
         }
     }
+
+    pub fn to_reply(&self) -> Response {
+        ErrorResponse::new("USER_NOT_FOUND", &self.message).into_reply(StatusCode::NOT_FOUND)
+    }
 }
 
 pub async fn get_user_details(user: String) -> Result<impl warp::Reply, Rejection> {
@@ -57,15 +68,7 @@ pub async fn get_user_details(user: String) -> Result<impl warp::Reply, Rejectio
 }
 
 pub async fn set_user_details(token: String, user: User) -> Result<impl Reply, Rejection> {
-    let valid_jwt = auth::decode_token(token).await.or_else(|err| {
-        log::error!("{:?}", err);
-        Err(warp::reject())
-    })?;
-
-    let uid = auth::get_uid(valid_jwt).await.or_else(|err| {
-        log::error!("{:?}", err);
-        Err(warp::reject())
-    })?;
+    let uid = get_uid_from_token(token).await?;
 
     let result = redis_manager::set::<User>("USERS", &uid, user);
     match result {
@@ -75,17 +78,40 @@ pub async fn set_user_details(token: String, user: User) -> Result<impl Reply, R
 }
 
 pub async fn get_user_from_token(token: String) -> Result<impl Reply, Rejection> {
+    let uid = get_uid_from_token(token).await?;
+
+    get_user_details(uid).await
+}
+
+pub async fn get_user(token: String) -> Result<User, Rejection> {
+    get_verified_user(token).await.map(|(_, user)| user)
+}
+
+/// The details of a user along with the uid verified from their token, which unlike the `id` of
+/// the details can't be chosen by the user.
+pub async fn get_verified_user(token: String) -> Result<(String, User), Rejection> {
+    let uid = get_uid_from_token(token).await?;
+
+    let user = redis_manager::get::<User>("USERS", uid.as_str())
+        .ok_or_else(|| reject::custom(UserFail::new(uid.clone())))?;
+    Ok((uid, user))
+}
+
+/// The solver caps of a user, falling back to the defaults for users without their own.
+pub fn get_solver_limits(uid: &str) -> SolverLimits {
+    redis_manager::get::<SolverLimits>(SOLVER_LIMITS_TABLE_NAME, uid).unwrap_or_default()
+}
+
+pub(crate) async fn get_uid_from_token(token: String) -> Result<String, Rejection> {
     let valid_jwt = auth::decode_token(token).await.or_else(|err| {
         log::error!("{:?}", err);
         Err(warp::reject())
     })?;
 
-    let uid = auth::get_uid(valid_jwt).await.or_else(|err| {
+    auth::get_uid(valid_jwt).await.or_else(|err| {
         log::error!("{:?}", err);
         Err(warp::reject())
-    })?;
-
-    get_user_details(uid).await
+    })
 }
 
 #[cfg(test)]